@@ -0,0 +1,67 @@
+use chrono::Local;
+use serde_json::{json, Value};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Rotate the audit log once it grows past this size, keeping a few old generations
+const MAX_LOG_BYTES: u64 = 5 * 1024 * 1024;
+const MAX_ROTATED_LOGS: usize = 3;
+
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+// Path of the audit log, if AUDIT_LOG is configured
+fn audit_log_path() -> Option<String> {
+    env::var("AUDIT_LOG").ok().filter(|path| !path.trim().is_empty())
+}
+
+// Record a request if auditing is enabled; failures are reported but never block the request
+pub fn record_request(url: &str, api_key: &str, body: &Value) {
+    if let Some(path) = audit_log_path() {
+        if let Err(err) = log_request(&path, url, api_key, body) {
+            eprintln!("Failed to write audit log {}: {}", path, err);
+        }
+    }
+}
+
+// Append one outgoing request to the audit log as a JSON line. The API key never reaches the file.
+fn log_request(path: &str, url: &str, api_key: &str, body: &Value) -> Result<(), io::Error> {
+    rotate_if_needed(path)?;
+
+    let sequence = SEQUENCE.fetch_add(1, Ordering::SeqCst) + 1;
+    let mut entry = json!({
+        "timestamp": Local::now().to_rfc3339(),
+        "sequence": sequence,
+        "url": url,
+        "authorization": "Bearer [REDACTED]",
+        "body": body,
+    })
+    .to_string();
+    if !api_key.is_empty() {
+        entry = entry.replace(api_key, "[REDACTED]");
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", entry)?;
+    file.flush()
+}
+
+fn rotate_if_needed(path: &str) -> Result<(), io::Error> {
+    let size = match fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(_) => return Ok(()),
+    };
+    if size < MAX_LOG_BYTES {
+        return Ok(());
+    }
+
+    // audit.log.2 -> audit.log.3, audit.log.1 -> audit.log.2, audit.log -> audit.log.1
+    for generation in (1..MAX_ROTATED_LOGS).rev() {
+        let from = format!("{}.{}", path, generation);
+        if fs::metadata(&from).is_ok() {
+            fs::rename(&from, format!("{}.{}", path, generation + 1))?;
+        }
+    }
+    fs::rename(path, format!("{}.1", path))
+}
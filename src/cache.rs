@@ -0,0 +1,78 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+// Cached responses live as individual files under this directory
+const DEFAULT_CACHE_DIR: &str = "cache/responses";
+
+pub fn cache_dir() -> PathBuf {
+    PathBuf::from(env::var("RESPONSE_CACHE_DIR").unwrap_or_else(|_| DEFAULT_CACHE_DIR.to_string()))
+}
+
+// The cached entries: the files directly in the cache directory, with their sizes.
+// Subdirectories are never entries. A missing directory is an empty cache.
+fn entries() -> Result<Vec<(PathBuf, u64)>, io::Error> {
+    let dir = match fs::read_dir(cache_dir()) {
+        Ok(dir) => dir,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let mut entries = Vec::new();
+    for entry in dir {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            entries.push((entry.path(), metadata.len()));
+        }
+    }
+    Ok(entries)
+}
+
+// Number of cached entries and their total size in bytes
+pub fn stats() -> Result<(usize, u64), io::Error> {
+    let entries = entries()?;
+    Ok((entries.len(), entries.iter().map(|(_, size)| size).sum()))
+}
+
+// Remove the entries `stats` counts, returning how many were deleted. The directory itself and
+// anything under it that isn't an entry are left alone, and the deletion is always confirmed,
+// since RESPONSE_CACHE_DIR can point anywhere.
+pub fn clear() -> Result<usize, io::Error> {
+    let entries = entries()?;
+    if entries.is_empty() {
+        return Ok(0);
+    }
+    print!("Delete {} cached responses in {}? (yes/no) ", entries.len(), cache_dir().display());
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    if !matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes") {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "clearing declined"));
+    }
+    let mut removed = 0;
+    for (path, _) in entries {
+        match fs::remove_file(&path) {
+            Ok(()) => removed += 1,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(removed)
+}
+
+// Human-readable byte count
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
@@ -0,0 +1,288 @@
+use crate::audit;
+use crate::errors;
+use crate::guard;
+use crate::http;
+use crate::images;
+use crate::models;
+use crate::provider::Provider;
+use crate::retry;
+use crate::tools;
+use crate::usage::Usage;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Set by --offline: every request is answered by `EchoBackend` instead of the API
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_offline(enabled: bool) {
+    OFFLINE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+// Something that can answer a conversation. The chat loop and commands go through this rather
+// than the HTTP client directly, so a canned backend can stand in for the API.
+pub trait ChatBackend {
+    // The assistant's reply to `messages`, with the token usage when the backend reports it
+    async fn complete(&self, messages: &[Value]) -> Result<(String, Option<Usage>), Box<dyn Error>>;
+
+    // Every alternative reply when several were asked for (`RequestOptions::n`), in order.
+    // Backends that can only give one reply return just that.
+    async fn complete_choices(&self, messages: &[Value]) -> Result<(Vec<String>, Option<Usage>), Box<dyn Error>> {
+        let (content, usage) = self.complete(messages).await?;
+        Ok((vec![content], usage))
+    }
+}
+
+// Optional request settings beyond the model and messages. Unset ones are left out of the body.
+#[derive(Default)]
+pub struct RequestOptions {
+    pub temperature: Option<f64>,
+    pub max_tokens: Option<u64>,
+    // Structured output format, from the active schema
+    pub response_format: Option<Value>,
+    // Function definitions the model may call, answered locally by `tools::dispatch`
+    pub tools: Option<Value>,
+    // Strings that end generation when produced; left out of the body when empty
+    pub stop: Vec<String>,
+    // How many alternative replies to ask for; only sent when above 1
+    pub n: Option<u32>,
+}
+
+impl RequestOptions {
+    fn apply(&self, body: &mut Value, verbose: bool) {
+        if verbose {
+            let unset = || "unset".to_string();
+            println!("Request settings: temperature {}, max_tokens {}", self.temperature.map_or_else(unset, |t| t.to_string()), self.max_tokens.map_or_else(unset, |t| t.to_string()));
+        }
+        if let Some(temperature) = self.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            body["max_tokens"] = json!(max_tokens);
+        }
+        if let Some(response_format) = &self.response_format {
+            body["response_format"] = response_format.clone();
+        }
+        if let Some(tools) = &self.tools {
+            body["tools"] = tools.clone();
+        }
+        if !self.stop.is_empty() {
+            body["stop"] = json!(self.stop);
+        }
+        if let Some(n) = self.n.filter(|&n| n > 1) {
+            body["n"] = json!(n);
+        }
+    }
+}
+
+// Chat completions body for `messages`, with the options and any tool settings applied
+pub fn request_body(model: &str, messages: &[Value], options: &RequestOptions, verbose: bool) -> Value {
+    let mut body = json!({
+        "model": model,
+        "messages": messages, // Pass the conversation log directly
+    });
+    options.apply(&mut body, verbose);
+    tools::apply_options(&mut body);
+    body
+}
+
+// The reply of every choice in a successful (non-streamed) chat completions response, ordered
+// by their index. A response without choices reads as an empty reply.
+pub fn parse_choices(response: &Value) -> Vec<String> {
+    let mut choices: Vec<(u64, String)> = response["choices"]
+        .as_array()
+        .map(|choices| {
+            choices
+                .iter()
+                .enumerate()
+                .map(|(position, choice)| (choice["index"].as_u64().unwrap_or(position as u64), choice["message"]["content"].as_str().unwrap_or_default().to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+    choices.sort_by_key(|(index, _)| *index);
+    choices.into_iter().map(|(_, content)| content).collect()
+}
+
+// The real API, reached through the shared HTTP client
+pub struct ApiBackend<'a> {
+    pub provider: Provider,
+    pub model: &'a str,
+    pub verbose: bool,
+    pub options: &'a RequestOptions,
+}
+
+impl ApiBackend<'_> {
+    async fn send(&self, api_key: &str, messages: &[Value]) -> Result<Value, Box<dyn Error>> {
+        // Ensure verbose logging is informative and correctly placed
+        if self.verbose {
+            println!("Conversation log for API request: {:?}", messages);
+        }
+
+        let url = self.provider.chat_url();
+        let body = request_body(self.model, messages, self.options, self.verbose);
+
+        // Audit logging records the exact body sent, independent of verbose mode
+        audit::record_request(&url, api_key, &body);
+
+        // Correctly structured API request for the chat model
+        let request = http::client().post(&url)
+            .header("Content-Type", "application/json")
+            .header("Authorization", format!("Bearer {}", api_key))
+            .json(&body);
+        let response = retry::send(request, retry::max_retries(), http::request_timeout()).await.map_err(errors::network_failure)?;
+
+        // Check the response status after the call, before attempting to consume the response body
+        if self.verbose {
+            println!("Response status: {}", response.status());
+        }
+
+        if response.status().is_success() {
+            Ok(response.json().await?)
+        } else {
+            // Handle error responses here
+            let status = response.status();
+            let error_message = response.text().await?;
+            Err(errors::api_failure(self.provider, status, &error_message))
+        }
+    }
+}
+
+impl ChatBackend for ApiBackend<'_> {
+    async fn complete(&self, messages: &[Value]) -> Result<(String, Option<Usage>), Box<dyn Error>> {
+        let (choices, usage) = self.complete_choices(messages).await?;
+        Ok((choices.into_iter().next().unwrap_or_default(), usage))
+    }
+
+    // Tool calls are answered and sent back until the model replies with text. The calls and
+    // their results only go with these requests; the conversation keeps just the final reply.
+    // Only the first choice's tool calls are followed.
+    async fn complete_choices(&self, messages: &[Value]) -> Result<(Vec<String>, Option<Usage>), Box<dyn Error>> {
+        let _in_flight = guard::acquire().await?;
+        let api_key = self.provider.api_key()?;
+        models::validate_model(self.provider, self.model, &api_key, self.verbose).await?;
+
+        let mut messages = messages.to_vec();
+        let mut usage: Option<Usage> = None;
+        for _ in 0..=tools::MAX_TOOL_ROUNDS {
+            let response = self.send(&api_key, &messages).await?;
+            if let Some(round) = Usage::from_json(&response["usage"]) {
+                let total = usage.get_or_insert_with(Usage::default);
+                total.prompt_tokens += round.prompt_tokens;
+                total.completion_tokens += round.completion_tokens;
+                total.total_tokens += round.total_tokens;
+            }
+
+            let choice = &response["choices"][0];
+            let calls = match choice["message"]["tool_calls"].as_array() {
+                Some(calls) if choice["finish_reason"] == "tool_calls" && !calls.is_empty() => calls.clone(),
+                _ => return Ok((parse_choices(&response), usage)),
+            };
+            messages.push(choice["message"].clone());
+            for call in &calls {
+                eprintln!("\r\x1b[K(Calling tool {})", call["function"]["name"].as_str().unwrap_or("?"));
+                messages.push(tools::dispatch(call));
+            }
+        }
+        Err(Box::new(io::Error::other(format!("the model was still calling tools after {} rounds", tools::MAX_TOOL_ROUNDS))))
+    }
+}
+
+// Canned answers for trying the chat loop, commands and rendering without a network or API
+// credits: the reply repeats the last user message back
+pub struct EchoBackend;
+
+impl ChatBackend for EchoBackend {
+    async fn complete(&self, messages: &[Value]) -> Result<(String, Option<Usage>), Box<dyn Error>> {
+        let last_user = messages.iter().rev().find(|message| message["role"] == "user");
+        let text = last_user.map(|message| images::text_of(&message["content"]).into_owned()).unwrap_or_default();
+        Ok((format!("(offline) You said: {}", text), None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // Answers with canned replies and keeps the messages it was sent
+    struct CannedBackend {
+        reply: &'static str,
+        seen: Mutex<Vec<Value>>,
+    }
+
+    impl ChatBackend for CannedBackend {
+        async fn complete(&self, messages: &[Value]) -> Result<(String, Option<Usage>), Box<dyn Error>> {
+            self.seen.lock().unwrap().extend_from_slice(messages);
+            Ok((self.reply.to_string(), Some(Usage { prompt_tokens: 3, completion_tokens: 2, total_tokens: 5 })))
+        }
+    }
+
+    #[tokio::test]
+    async fn single_reply_backend_offers_one_choice() {
+        let backend = CannedBackend { reply: "Hello!", seen: Mutex::new(Vec::new()) };
+        let messages = vec![json!({"role": "user", "content": "hi"})];
+        let (choices, usage) = backend.complete_choices(&messages).await.unwrap();
+        assert_eq!(choices, vec!["Hello!".to_string()]);
+        assert_eq!(usage.unwrap().total_tokens, 5);
+        assert_eq!(*backend.seen.lock().unwrap(), messages);
+    }
+
+    #[tokio::test]
+    async fn echo_backend_repeats_the_last_user_message() {
+        let messages = vec![
+            json!({"role": "user", "content": "first"}),
+            json!({"role": "assistant", "content": "ok"}),
+            json!({"role": "user", "content": "second"}),
+            json!({"role": "system", "content": "addendum"}),
+        ];
+        let (reply, usage) = EchoBackend.complete(&messages).await.unwrap();
+        assert_eq!(reply, "(offline) You said: second");
+        assert!(usage.is_none());
+    }
+
+    #[test]
+    fn choices_are_ordered_by_index() {
+        let response = json!({"choices": [
+            {"index": 2, "message": {"content": "third"}},
+            {"index": 0, "message": {"content": "first"}},
+            {"index": 1, "message": {"content": "second"}},
+        ]});
+        assert_eq!(parse_choices(&response), vec!["first", "second", "third"]);
+    }
+
+    #[test]
+    fn missing_choices_and_content_read_as_empty() {
+        assert!(parse_choices(&json!({"usage": {}})).is_empty());
+        let response = json!({"choices": [{"message": {"role": "assistant", "content": null}}, {"message": {"content": "text"}}]});
+        assert_eq!(parse_choices(&response), vec!["", "text"]);
+    }
+
+    #[test]
+    fn unset_options_are_left_out_of_the_body() {
+        let messages = vec![json!({"role": "user", "content": "hi"})];
+        for n in [None, Some(0), Some(1)] {
+            let options = RequestOptions { n, ..RequestOptions::default() };
+            let body = request_body("gpt-4o-mini", &messages, &options, false);
+            assert!(body.get("n").is_none(), "n = {:?}", n);
+            assert!(body.get("stop").is_none());
+            assert!(body.get("temperature").is_none());
+            assert_eq!(body["model"], "gpt-4o-mini");
+            assert_eq!(body["messages"], json!(messages));
+        }
+    }
+
+    #[test]
+    fn set_options_are_sent() {
+        let options = RequestOptions { temperature: Some(0.5), max_tokens: Some(100), stop: vec!["###".to_string()], n: Some(3), ..RequestOptions::default() };
+        let body = request_body("gpt-4o-mini", &[], &options, false);
+        assert_eq!(body["temperature"], 0.5);
+        assert_eq!(body["max_tokens"], 100);
+        assert_eq!(body["stop"], json!(["###"]));
+        assert_eq!(body["n"], 3);
+    }
+}
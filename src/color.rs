@@ -0,0 +1,53 @@
+use std::env;
+use std::io::{self, IsTerminal};
+use std::sync::OnceLock;
+
+const BOLD_GREEN: &str = "\x1b[1;32m";
+const BOLD_BLUE: &str = "\x1b[1;34m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+// Colour is used only on a terminal, and never when NO_COLOR is set to anything
+// (https://no-color.org). Checked once, since neither changes during a run.
+fn allowed(terminal: bool) -> bool {
+    terminal && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+}
+
+pub fn stdout_enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| allowed(io::stdout().is_terminal()))
+}
+
+fn stderr_enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| allowed(io::stderr().is_terminal()))
+}
+
+fn paint(text: &str, color: &str, enabled: bool) -> String {
+    if enabled {
+        format!("{}{}{}", color, text, RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+// The "You:" input prompt
+pub fn prompt(text: &str) -> String {
+    paint(text, BOLD_GREEN, stdout_enabled())
+}
+
+// The "Bot:" prefix before a response
+pub fn bot() -> String {
+    paint("Bot:", BOLD_BLUE, stdout_enabled())
+}
+
+// A notice that something is close to a limit
+pub fn warning(text: &str) -> String {
+    paint(text, YELLOW, stdout_enabled())
+}
+
+// An error message, which goes to stderr
+pub fn error(text: &str) -> String {
+    paint(text, RED, stderr_enabled())
+}
@@ -0,0 +1,1258 @@
+use crate::cache;
+use crate::color;
+use crate::diff;
+use crate::editor;
+use crate::export;
+use crate::images;
+use crate::persona;
+use crate::profile;
+use crate::provider::Provider;
+use crate::recall::RecallIndex;
+use crate::refs;
+use crate::schema::Schema;
+use crate::search;
+use crate::shell;
+use crate::storage;
+use crate::summarize;
+use crate::tools;
+use crate::trim;
+use crate::usage;
+use crate::render::{self, OutputFormat, Renderer};
+use crate::{build_request, fetch_response, is_blank_response, query_gpt, query_gpt_with, read_initial_prompt, request_extras, terminal_width, ChatState, Spinner, MAX_STOP_SEQUENCES};
+use regex::Regex;
+use serde_json::{json, Value};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::io::{self, Write};
+use std::time::Duration;
+
+// Most alternative replies /choices asks for in one turn
+const MAX_CHOICES: u32 = 8;
+
+pub enum CommandResult {
+    Continue,
+    Exit,
+}
+
+// Dispatch a slash command (without the leading '/') against the live chat state
+pub async fn handle_command(command: &str, state: &mut ChatState) -> CommandResult {
+    let mut parts = command.split_whitespace();
+    match parts.next() {
+        Some("exit") | Some("quit") => return CommandResult::Exit,
+        Some("compare") => {
+            match compare_args(&command["compare".len()..]) {
+                Some((model_a, model_b, prompt)) => compare_models(state, model_a, model_b, prompt).await,
+                _ => println!("Usage: /compare <modelA> <modelB> <prompt>"),
+            }
+        }
+        Some("recall") => {
+            match parts.next() {
+                Some("on") => state.recall_enabled = true,
+                Some("off") => state.recall_enabled = false,
+                Some("rebuild") => state.recall_enabled = true,
+                _ => state.recall_enabled = !state.recall_enabled,
+            }
+            if state.recall_enabled {
+                if state.recall_index.len() == 0 {
+                    state.recall_index = RecallIndex::load();
+                }
+                match state.recall_index.rebuild(state.verbose).await {
+                    Ok(added) => println!("Recall enabled ({} snippets indexed, {} new).", state.recall_index.len(), added),
+                    Err(err) => eprintln!("Failed to build recall index: {}", err),
+                }
+            } else {
+                println!("Recall disabled.");
+            }
+        }
+        Some("format") => match parts.next().map(OutputFormat::parse) {
+            Some(Some(format)) => {
+                state.format = format;
+                state.format_chosen = true;
+                println!("Output format set to {}.", format.name());
+            }
+            _ => println!("Usage: /format plain|markdown|raw (current: {})", state.format.name()),
+        },
+        Some("model") => match parts.next() {
+            Some(model) => {
+                state.model = model.to_string();
+                println!("Model set to {}.", state.model);
+            }
+            None => println!("Current model: {}", state.model),
+        },
+        Some("suffix") => {
+            let instruction = command["suffix".len()..].trim();
+            match instruction {
+                "" => match state.addendum("suffix") {
+                    Some(text) => println!("Active suffix directive: {}", text),
+                    None => println!("No suffix directive set. Usage: /suffix <instruction> | /suffix clear"),
+                },
+                "clear" | "off" => {
+                    if state.clear_addendum("suffix") {
+                        println!("Suffix directive cleared.");
+                    } else {
+                        println!("No suffix directive was set.");
+                    }
+                }
+                _ => {
+                    state.set_addendum("suffix", format!("Always end your response as follows: {}", instruction));
+                    println!("Suffix directive set.");
+                }
+            }
+        }
+        Some("checkpoint") => match parts.next() {
+            Some(name) => create_checkpoint(state, name),
+            None => println!("Usage: /checkpoint <name>"),
+        },
+        Some("restore") => match parts.next() {
+            Some(name) => restore_checkpoint(state, name),
+            None => println!("Usage: /restore <name>"),
+        },
+        Some("checkpoints") => list_checkpoints(state),
+        Some("lang") => match parts.next() {
+            Some("off") | Some("clear") => {
+                state.language = None;
+                if state.clear_addendum("lang") {
+                    println!("Response language cleared.");
+                } else {
+                    println!("No response language was set.");
+                }
+            }
+            Some(language) => {
+                state.set_addendum("lang", format!("Always respond in the language '{}', whatever language the user writes in.", language));
+                state.language = Some(language.to_string());
+                println!("Responses will be in '{}'.", language);
+            }
+            None => match &state.language {
+                Some(language) => println!("Response language: {}", language),
+                None => println!("No response language set. Usage: /lang <code> | /lang off"),
+            },
+        },
+        Some("parallel-tools") => {
+            match parts.next() {
+                Some("on") => tools::set_parallel_tool_calls(Some(true)),
+                Some("off") => tools::set_parallel_tool_calls(Some(false)),
+                Some("default") => tools::set_parallel_tool_calls(None),
+                Some(_) => {
+                    println!("Usage: /parallel-tools on|off|default");
+                    return CommandResult::Continue;
+                }
+                None => {}
+            }
+            println!("Parallel tool calls: {}", parallel_tools_label());
+        }
+        Some("schema") => match parts.next() {
+            Some("off") | Some("clear") => {
+                if state.schema.take().is_some() {
+                    println!("Structured output schema cleared.");
+                } else {
+                    println!("No structured output schema was set.");
+                }
+            }
+            Some(path) => match Schema::load(path) {
+                Ok(schema) => {
+                    println!("Responses will follow the schema in {}.", schema.path);
+                    state.schema = Some(schema);
+                }
+                Err(err) => eprintln!("Failed to load schema: {}", err),
+            },
+            None => match &state.schema {
+                Some(schema) => println!("Structured output schema: {}", schema.path),
+                None => println!("No structured output schema set. Usage: /schema <path> | /schema off"),
+            },
+        },
+        Some("choices") => match parts.next().map(str::parse::<u32>) {
+            Some(Ok(count)) if (1..=MAX_CHOICES).contains(&count) => {
+                state.choices = count;
+                match count {
+                    1 => println!("Asking for a single reply."),
+                    _ => println!("Asking for {} replies per message; you'll pick one to keep.", count),
+                }
+            }
+            None => println!("Replies per message: {}", state.choices),
+            _ => println!("Usage: /choices <1-{}>", MAX_CHOICES),
+        },
+        Some("stop") => set_stop(state, command["stop".len()..].trim()),
+        Some("transform") => set_transform(state, command["transform".len()..].trim()),
+        Some("persona") => match (parts.next(), parts.next()) {
+            (None, _) => list_personas(state),
+            (Some(name), reset) if reset.is_none() || reset == Some("reset") => switch_persona(state, name, reset.is_some()),
+            _ => println!("Usage: /persona [<name> [reset]]"),
+        },
+        Some("personas") => list_personas(state),
+        Some("config") => show_config(state),
+        Some("cache") => match parts.next() {
+            Some("stats") | None => match cache::stats() {
+                Ok((0, _)) => println!("Response cache is empty ({}).", cache::cache_dir().display()),
+                Ok((count, bytes)) => println!("Response cache: {} entries, {} ({}).", count, cache::format_size(bytes), cache::cache_dir().display()),
+                Err(err) => eprintln!("Failed to read response cache: {}", err),
+            },
+            Some("clear") => match cache::clear() {
+                Ok(count) => println!("Cleared {} cached responses.", count),
+                Err(err) => eprintln!("Failed to clear response cache: {}", err),
+            },
+            Some(_) => println!("Usage: /cache stats | /cache clear"),
+        },
+        Some("image") => match command["image".len()..].trim() {
+            "" => println!("Usage: /image <path>"),
+            path => stage_image(state, path),
+        },
+        Some("sh") => stage_shell_output(state, command["sh".len()..].trim()),
+        Some("save") => match parts.next() {
+            Some(name) => save_session(state, name),
+            None => save_session(state, &storage::timestamped_name()),
+        },
+        Some("load") => match parts.next() {
+            Some(name) => load_session(state, name),
+            None => println!("Usage: /load <name>"),
+        },
+        Some("duplicate") => match parts.next() {
+            Some(name) => duplicate_session(state, name),
+            None => println!("Usage: /duplicate <name>"),
+        },
+        Some("sessions") => list_sessions(),
+        Some("gist") => match parts.next() {
+            None | Some("private") => share_gist(state, false).await,
+            Some("public") => share_gist(state, true).await,
+            Some(_) => println!("Usage: /gist [private|public]"),
+        },
+        Some("rename") => match (parts.next(), parts.next()) {
+            (Some(old), Some(new)) => rename_session(state, old, new),
+            _ => println!("Usage: /rename <old> <new>"),
+        },
+        Some("delete") => match parts.next() {
+            Some(name) => delete_session(state, name),
+            None => println!("Usage: /delete <name>"),
+        },
+        Some("search-all") => match search::parse_query(&command["search-all".len()..]) {
+            Ok(matcher) => match storage::open_store().and_then(|store| store.search(&matcher)) {
+                Ok(hits) => search::print_hits(&hits),
+                Err(err) => eprintln!("Search failed: {}", err),
+            },
+            Err(err) => println!("Usage: /search-all [-r|--regex] [-c|--case-sensitive] <term> ({})", err),
+        },
+        Some("speed") => match parts.next().map(str::parse::<u64>) {
+            Some(Ok(delay)) => {
+                state.typing_delay_ms = delay;
+                if delay == 0 {
+                    println!("Typewriter effect disabled.");
+                } else {
+                    println!("Typewriter delay set to {} ms per character.", delay);
+                }
+            }
+            _ => println!("Usage: /speed <ms> (current: {} ms, 0 disables)", state.typing_delay_ms),
+        },
+        Some("provider") => match parts.next() {
+            Some(name) => match Provider::from_name(name) {
+                Some(provider) => {
+                    state.provider = provider;
+                    println!("Provider set to {}.", provider.name());
+                }
+                None => println!("Unknown provider '{}'. Available: openai, openrouter, anthropic, ollama", name),
+            },
+            None => println!("Current provider: {}", state.provider.name()),
+        },
+        Some("wc") => {
+            state.live_counts = match parts.next() {
+                Some("on") => true,
+                Some("off") => false,
+                _ => !state.live_counts,
+            };
+            println!("Live input counts {}.", if state.live_counts { "enabled" } else { "disabled" });
+        }
+        Some("tokens") => show_context_size(state),
+        Some("usage") => match parts.next() {
+            Some("total") => {
+                let (totals, cost) = usage::session_totals();
+                println!("This session: prompt {} / completion {} / total {} tokens, about ${:.4}", totals.prompt_tokens, totals.completion_tokens, totals.total_tokens, cost);
+            }
+            toggle => {
+                state.show_usage = match toggle {
+                    Some("on") => true,
+                    Some("off") => false,
+                    _ => !state.show_usage,
+                };
+                println!("Usage display {}.", if state.show_usage { "enabled" } else { "disabled" });
+            }
+        },
+        Some("datetime") => {
+            state.inject_datetime = match parts.next() {
+                Some("on") => true,
+                Some("off") => false,
+                _ => !state.inject_datetime,
+            };
+            if !state.inject_datetime {
+                state.clear_addendum("datetime");
+            }
+            println!("Date and time injection {}.", if state.inject_datetime { "enabled" } else { "disabled" });
+        }
+        Some("json") => {
+            state.json_mode = match parts.next() {
+                Some("on") => true,
+                Some("off") => false,
+                _ => !state.json_mode,
+            };
+            println!("JSON mode {}.", if state.json_mode { "enabled" } else { "disabled" });
+            if state.json_mode && state.schema.is_some() {
+                println!("(The active schema takes precedence until /schema off.)");
+            }
+        }
+        Some("stream") => {
+            state.stream = match parts.next() {
+                Some("on") => true,
+                Some("off") => false,
+                _ => !state.stream,
+            };
+            println!("Streaming {}.", if state.stream { "enabled" } else { "disabled" });
+        }
+        Some("continue") => continue_response(state).await,
+        Some("history") => show_history(state),
+        Some("clear") => clear_conversation(state),
+        Some("undo") => undo_turn(state),
+        Some("reload") => reload_prompt(state),
+        Some("ref") => match (parts.next().map(str::parse::<usize>), parts.next()) {
+            (Some(Ok(index)), copy) if copy.is_none() || copy == Some("copy") => reference_message(state, index, copy.is_some()),
+            _ => println!("Usage: /ref <index> [copy] (see /history)"),
+        },
+        Some("goto") => match parts.next() {
+            Some(reference) => goto_reference(state, reference),
+            None => println!("Usage: /goto <reference>"),
+        },
+        Some("replay-turn") => match parts.next().map(str::parse::<usize>) {
+            Some(Ok(index)) => replay_turn(state, index).await,
+            _ => println!("Usage: /replay-turn <index> (see /history)"),
+        },
+        Some("compress") => {
+            let mut spinner = Spinner::start();
+            let result = summarize::compress(state).await;
+            spinner.stop().await;
+            match result {
+                Ok(0) => println!("Nothing old enough to summarize."),
+                Ok(count) => println!("Folded {} messages into a summary.", count),
+                Err(err) => eprintln!("Failed to summarize: {}", err),
+            }
+        }
+        Some("improve-prompt") => improve_system_prompt(state, command["improve-prompt".len()..].trim()).await,
+        Some("stability") => match parts.next().map(str::parse::<usize>) {
+            Some(Ok(runs)) if (2..=MAX_STABILITY_RUNS).contains(&runs) => stability_check(state, runs).await,
+            _ => println!("Usage: /stability <n> (2-{} runs)", MAX_STABILITY_RUNS),
+        },
+        Some("regenerate") | Some("regen") => regenerate(state).await,
+        Some("regen-diff") => regenerate_with_diff(state).await,
+        Some("profile") => match parts.next() {
+            Some("edit") => match editor::open_in_editor(Path::new(profile::USER_PROFILE_PATH)) {
+                Ok(()) => println!("Profile saved."),
+                Err(err) => eprintln!("Failed to edit profile: {}", err),
+            },
+            _ => match profile::read_profile() {
+                Ok(data) if data.trim().is_empty() => println!("The user profile is empty."),
+                Ok(data) => match profile::pretty_profile(&data) {
+                    Some(pretty) => println!("{}", pretty),
+                    None => println!("{}", Renderer::new(state.format).render(data.trim())),
+                },
+                Err(err) => eprintln!("Failed to read {}: {}", profile::USER_PROFILE_PATH, err),
+            },
+        },
+        Some("profile-opcodes") => show_profile_opcodes(),
+        Some("open") => match editor::edit_conversation(&state.conversation_log) {
+            Ok(Some(edited)) => {
+                println!("Conversation updated from editor ({} messages).", edited.len());
+                state.conversation_log = edited;
+            }
+            Ok(None) => println!("No changes made."),
+            Err(err) => eprintln!("Failed to edit conversation: {}", err),
+        },
+        _ => println!("Unknown command: /{}", command),
+    }
+    CommandResult::Continue
+}
+
+// Run each line of a startup script through the command dispatcher. Problems are reported
+// as warnings so a broken script never prevents the chat from starting.
+pub async fn run_init_script(path: &str, state: &mut ChatState) {
+    let script = match fs::read_to_string(path) {
+        Ok(script) => script,
+        Err(err) => {
+            eprintln!("Warning: failed to read init script {}: {}", path, err);
+            return;
+        }
+    };
+
+    for (number, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.strip_prefix('/') {
+            Some(command) => {
+                if state.verbose {
+                    println!("init: {}", line);
+                }
+                if let CommandResult::Exit = handle_command(command, state).await {
+                    eprintln!("Warning: {}:{}: ignoring {} in init script", path, number + 1, line);
+                }
+            }
+            None => eprintln!("Warning: {}:{}: not a command: {}", path, number + 1, line),
+        }
+    }
+}
+
+fn save_session(state: &mut ChatState, name: &str) {
+    if let Err(err) = storage::validate_name(name) {
+        eprintln!("Invalid session name: {}", err);
+        return;
+    }
+    match storage::open_store().and_then(|store| store.save(name, &state.conversation_log, &state.model, usage::session_totals(), &state.checkpoints)) {
+        Ok(()) => {
+            println!("Saved {} messages as '{}'.", state.conversation_log.len(), name);
+            state.session_name = Some(name.to_string());
+            state.wal.compact(name, &state.conversation_log);
+        }
+        Err(err) => eprintln!("Failed to save session '{}': {}", name, err),
+    }
+}
+
+fn load_session(state: &mut ChatState, name: &str) {
+    if let Err(err) = storage::validate_name(name) {
+        eprintln!("Invalid session name: {}", err);
+        return;
+    }
+    let loaded = storage::open_store().and_then(|store| {
+        let messages = match store.load(name)? {
+            Some(messages) => messages,
+            None => return Ok(None),
+        };
+        Ok(Some((messages, store.load_usage(name), store.load_checkpoints(name)?)))
+    });
+    match loaded {
+        Ok(Some((messages, usage, checkpoints))) => {
+            println!("Loaded '{}' ({} messages).", name, messages.len());
+            match usage {
+                Ok(Some((totals, cost))) => usage::restore(totals, cost),
+                Ok(None) => {}
+                Err(err) => eprintln!("Failed to read the usage saved with '{}': {}", name, err),
+            }
+            state.conversation_log = messages;
+            state.session_name = Some(name.to_string());
+            // Checkpoints belong to the conversation they were taken from, and come back with it
+            state.checkpoints = checkpoints;
+        }
+        Ok(None) => println!("No saved session named '{}'.", name),
+        Err(err) => eprintln!("Failed to load session '{}': {}", name, err),
+    }
+}
+
+// Upload the conversation as Markdown to a GitHub gist and print its URL
+async fn share_gist(state: &ChatState, public: bool) {
+    let visibility = if public { "public" } else { "private (unlisted)" };
+    if !confirm(&format!("Upload {} messages to a {} gist? (yes/no) ", state.conversation_log.len(), visibility)) {
+        println!("Cancelled.");
+        return;
+    }
+    let mut spinner = Spinner::start();
+    let result = export::create_gist(&export::to_markdown(&state.conversation_log), public).await;
+    spinner.stop().await;
+    match result {
+        Ok(url) => println!("Gist created: {}", url),
+        Err(err) => eprintln!("Failed to create gist: {}", err),
+    }
+}
+
+// Save a copy of the conversation under a new name, staying on the current session
+fn duplicate_session(state: &ChatState, name: &str) {
+    if let Err(err) = storage::validate_name(name) {
+        eprintln!("Invalid session name: {}", err);
+        return;
+    }
+    let result = storage::open_store().and_then(|store| {
+        if store.exists(name)? {
+            return Err(format!("a session named '{}' already exists", name).into());
+        }
+        store.save(name, &state.conversation_log, &state.model, usage::session_totals(), &state.checkpoints)
+    });
+    match result {
+        Ok(()) => println!("Duplicated {} messages to '{}'.", state.conversation_log.len(), name),
+        Err(err) => eprintln!("Failed to duplicate session: {}", err),
+    }
+}
+
+fn rename_session(state: &mut ChatState, old: &str, new: &str) {
+    if let Err(err) = storage::validate_name(old).and_then(|_| storage::validate_name(new)) {
+        eprintln!("Invalid session name: {}", err);
+        return;
+    }
+    let result = storage::open_store().and_then(|store| {
+        if store.exists(new)? {
+            return Err(format!("a session named '{}' already exists", new).into());
+        }
+        store.rename(old, new)
+    });
+    match result {
+        Ok(true) => {
+            if state.session_name.as_deref() == Some(old) {
+                state.session_name = Some(new.to_string());
+            }
+            update_recall_index(state, |index| index.rename_session(old, new));
+            println!("Renamed '{}' to '{}'.", old, new);
+        }
+        Ok(false) => println!("No saved session named '{}'.", old),
+        Err(err) => eprintln!("Failed to rename session: {}", err),
+    }
+}
+
+fn delete_session(state: &mut ChatState, name: &str) {
+    if let Err(err) = storage::validate_name(name) {
+        eprintln!("Invalid session name: {}", err);
+        return;
+    }
+    if state.session_name.as_deref() == Some(name) {
+        println!("Warning: '{}' is the active session. The open conversation stays in memory but its saved copy will be gone.", name);
+    }
+    if !confirm(&format!("Delete session '{}'? This cannot be undone. (yes/no) ", name)) {
+        println!("Cancelled.");
+        return;
+    }
+    match storage::open_store().and_then(|store| store.delete(name)) {
+        Ok(true) => {
+            if state.session_name.as_deref() == Some(name) {
+                state.session_name = None;
+            }
+            update_recall_index(state, |index| index.remove_session(name));
+            println!("Deleted '{}'.", name);
+        }
+        Ok(false) => println!("No saved session named '{}'.", name),
+        Err(err) => eprintln!("Failed to delete session: {}", err),
+    }
+}
+
+// Apply a change to the recall index, both in memory and on disk
+fn update_recall_index(state: &mut ChatState, change: impl FnOnce(&mut RecallIndex)) {
+    if !RecallIndex::exists_on_disk() {
+        return;
+    }
+    if state.recall_index.len() == 0 {
+        state.recall_index = RecallIndex::load();
+    }
+    change(&mut state.recall_index);
+    if let Err(err) = state.recall_index.save() {
+        eprintln!("Failed to update recall index: {}", err);
+    }
+}
+
+// Ask a yes/no question on stdin
+fn confirm(question: &str) -> bool {
+    print!("{}", question);
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    let _ = io::stdin().read_line(&mut answer);
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+fn list_sessions() {
+    match storage::open_store().and_then(|store| store.list()) {
+        Ok(sessions) if sessions.is_empty() => println!("No saved sessions."),
+        Ok(sessions) => {
+            for session in sessions {
+                let updated = session.updated_at.map(|time| time.format("%Y-%m-%d %H:%M").to_string()).unwrap_or_default();
+                println!("{:<24} {:>4} messages  {}", session.name, session.messages, updated);
+            }
+        }
+        Err(err) => eprintln!("Failed to list sessions: {}", err),
+    }
+}
+
+// The opcodes and difference count behind the last profile update's keep/rollback decision
+fn show_profile_opcodes() {
+    let report = match profile::last_update() {
+        Some(report) => report,
+        None => {
+            println!("No profile update has run yet this session (enable with PROFILE_UPDATES=1).");
+            return;
+        }
+    };
+    for (tag, old_start, old_end, new_start, new_end) in &report.opcodes {
+        println!("{:<8} old[{}..{}]  new[{}..{}]", tag, old_start, old_end, new_start, new_end);
+    }
+    println!(
+        "{} of {} opcodes are differences, similarity {:.2} (minimum {:.2}): update was {}.",
+        report.differences,
+        report.opcodes.len(),
+        report.similarity,
+        report.min_similarity,
+        if report.rolled_back { "rolled back" } else { "kept" }
+    );
+}
+
+fn parallel_tools_label() -> &'static str {
+    match tools::parallel_tool_calls() {
+        Some(true) => "on",
+        Some(false) => "off",
+        None => "API default",
+    }
+}
+
+// "/transform <pattern> <replacement>" adds a substitution for later responses (an empty
+// replacement deletes matches), "/transform clear" removes them all
+fn set_transform(state: &mut ChatState, args: &str) {
+    match args {
+        "" => {
+            if state.transforms.is_empty() {
+                println!("No transforms set. Usage: /transform <pattern> <replacement> | /transform clear");
+            }
+            for (index, (pattern, replacement)) in state.transforms.iter().enumerate() {
+                println!("{}. {} -> {:?}", index + 1, pattern.as_str(), replacement);
+            }
+        }
+        "clear" => {
+            let count = state.transforms.len();
+            state.transforms.clear();
+            println!("Cleared {} transform{}.", count, if count == 1 { "" } else { "s" });
+        }
+        _ => {
+            let (pattern, replacement) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+            match Regex::new(pattern) {
+                Ok(regex) => {
+                    state.transforms.push((regex, replacement.trim_start().to_string()));
+                    println!("Transform added; it applies to responses from now on.");
+                }
+                Err(err) => eprintln!("Invalid regex, transform not added: {}", err),
+            }
+        }
+    }
+}
+
+// Start over in the same process, keeping only the opening system prompt if there is one
+fn clear_conversation(state: &mut ChatState) {
+    let keep = usize::from(state.conversation_log.first().is_some_and(|message| message["role"] == "system"));
+    let removed = state.conversation_log.len() - keep;
+    state.conversation_log.truncate(keep);
+    if keep == 1 {
+        println!("Cleared {} messages; the system prompt is kept.", removed);
+    } else {
+        println!("Cleared {} messages.", removed);
+    }
+}
+
+// Rewind one turn: the last response and the message it answered. A trailing user message
+// without a response is removed on its own; system messages are never touched.
+fn undo_turn(state: &mut ChatState) {
+    let mut removed = 0;
+    for role in ["assistant", "user"] {
+        if state.conversation_log.last().is_some_and(|message| message["role"] == role) {
+            state.conversation_log.pop();
+            removed += 1;
+        }
+    }
+    if removed == 0 {
+        println!("Nothing to undo.");
+    } else {
+        println!("Removed {} message{}; {} left.", removed, if removed == 1 { "" } else { "s" }, state.conversation_log.len());
+    }
+}
+
+// Re-read the prompt file into the system message. A file that can't be read, or is empty,
+// leaves the current prompt in place.
+fn reload_prompt(state: &mut ChatState) {
+    let prompt = match read_initial_prompt(&state.prompt_path, state.verbose) {
+        Ok(prompt) if !prompt.trim().is_empty() => prompt,
+        Ok(_) => {
+            println!("{} is empty; keeping the current system prompt.", state.prompt_path);
+            return;
+        }
+        Err(err) => {
+            eprintln!("Failed to read {}: {}; keeping the current system prompt.", state.prompt_path, err);
+            return;
+        }
+    };
+    let current = state.conversation_log.iter().find(|message| message["role"] == "system").and_then(|message| message["content"].as_str());
+    if current == Some(prompt.as_str()) {
+        println!("System prompt reloaded from {} (unchanged).", state.prompt_path);
+        return;
+    }
+    state.set_system_prompt(&prompt);
+    if let Some(name) = state.persona.take() {
+        println!("System prompt reloaded from {}, replacing persona '{}'.", state.prompt_path, name);
+    } else {
+        println!("System prompt reloaded from {} (changed).", state.prompt_path);
+    }
+}
+
+// "/stop <sequence>" adds a stop sequence (\n and \t stand for a newline and a tab),
+// "/stop" lists them and "/stop clear" removes them all
+fn set_stop(state: &mut ChatState, sequence: &str) {
+    match sequence {
+        "" if state.stop.is_empty() => println!("No stop sequences set. Usage: /stop <sequence> | /stop clear"),
+        "" => {
+            for sequence in &state.stop {
+                println!("{:?}", sequence);
+            }
+        }
+        "clear" => {
+            state.stop.clear();
+            println!("Stop sequences cleared.");
+        }
+        _ if state.stop.len() >= MAX_STOP_SEQUENCES => println!("At most {} stop sequences can be set; /stop clear removes them.", MAX_STOP_SEQUENCES),
+        _ => {
+            let sequence = sequence.replace("\\n", "\n").replace("\\t", "\t");
+            println!("Generation will stop at {:?}.", sequence);
+            state.stop.push(sequence);
+        }
+    }
+}
+
+fn list_personas(state: &ChatState) {
+    let personas = persona::configured();
+    if personas.is_empty() {
+        println!("No personas found. Add system_prompts/<name>.md, or set PERSONAS=name=path,...");
+    }
+    for (name, path) in personas {
+        let marker = if state.persona.as_deref() == Some(name.as_str()) { "*" } else { " " };
+        println!("{} {:<16} {}", marker, name, path);
+    }
+}
+
+// Swap in a persona's system prompt; with `reset` the rest of the conversation is cleared too
+fn switch_persona(state: &mut ChatState, name: &str, reset: bool) {
+    let prompt = match persona::load(name) {
+        Ok(prompt) => prompt,
+        Err(err) => {
+            eprintln!("Can't switch persona: {}", err);
+            return;
+        }
+    };
+    if reset {
+        state.conversation_log.clear();
+    }
+    state.set_system_prompt(&prompt);
+    state.persona = Some(name.to_string());
+    if reset {
+        println!("Switched to persona '{}' with a fresh conversation.", name);
+    } else {
+        println!("Switched to persona '{}'.", name);
+    }
+}
+
+// Current session settings
+fn show_config(state: &ChatState) {
+    let on_off = |enabled: bool| if enabled { "on" } else { "off" };
+    println!("provider       {}", state.provider.name());
+    println!("model          {}", state.model);
+    println!("temperature    {}", state.temperature);
+    println!("max tokens     {}", state.max_tokens.map_or("(no cap)".to_string(), |tokens| tokens.to_string()));
+    println!("context budget {}", state.context_budget.map_or("(unlimited)".to_string(), |budget| format!("{} tokens", budget)));
+    println!("format         {}", state.format.name());
+    println!("stream         {}", on_off(state.stream));
+    println!("typing delay   {} ms", state.typing_delay_ms);
+    println!("recall         {}", on_off(state.recall_enabled));
+    println!("persona        {}", state.persona.as_deref().unwrap_or("(none)"));
+    println!("language       {}", state.language.as_deref().unwrap_or("(not set)"));
+    println!("suffix         {}", state.addendum("suffix").unwrap_or("(not set)"));
+    println!("json mode      {}", on_off(state.json_mode));
+    println!("choices        {}", state.choices);
+    println!("stop           {}", if state.stop.is_empty() { "(none)".to_string() } else { format!("{:?}", state.stop) });
+    println!("datetime       {}", on_off(state.inject_datetime));
+    println!("parallel tools {}", parallel_tools_label());
+    println!("schema         {}", state.schema.as_ref().map_or("(none)", |schema| schema.path.as_str()));
+    println!("session        {}", state.session_name.as_deref().unwrap_or("(unsaved)"));
+}
+
+// Snapshot the conversation under a name, replacing any earlier checkpoint with that name
+fn create_checkpoint(state: &mut ChatState, name: &str) {
+    let snapshot = state.conversation_log.clone();
+    match state.checkpoints.iter_mut().find(|(existing, _)| existing == name) {
+        Some(entry) => {
+            entry.1 = snapshot;
+            println!("Checkpoint '{}' updated ({} messages).", name, state.conversation_log.len());
+        }
+        None => {
+            state.checkpoints.push((name.to_string(), snapshot));
+            println!("Checkpoint '{}' created ({} messages).", name, state.conversation_log.len());
+        }
+    }
+}
+
+// Revert the conversation to a checkpoint. The checkpoint is kept so it can be restored again.
+fn restore_checkpoint(state: &mut ChatState, name: &str) {
+    match state.checkpoints.iter().find(|(existing, _)| existing == name) {
+        Some((_, snapshot)) => {
+            state.conversation_log = snapshot.clone();
+            println!("Restored checkpoint '{}' ({} messages).", name, state.conversation_log.len());
+        }
+        None => println!("No checkpoint named '{}'. Use /checkpoints to list them.", name),
+    }
+}
+
+fn list_checkpoints(state: &ChatState) {
+    if state.checkpoints.is_empty() {
+        println!("No checkpoints. Create one with /checkpoint <name>.");
+        return;
+    }
+    for (name, snapshot) in &state.checkpoints {
+        println!("{:<24} {:>4} messages", name, snapshot.len());
+    }
+}
+
+// Run a shell command after explicit confirmation and stage its output for the next message
+fn stage_shell_output(state: &mut ChatState, shell_command: &str) {
+    if !shell::shell_commands_allowed() {
+        println!("Shell commands are disabled. Set ALLOW_SHELL_COMMANDS=1 to enable /sh.");
+        return;
+    }
+    if shell_command.is_empty() {
+        println!("Usage: /sh <command>");
+        return;
+    }
+
+    if !confirm(&format!("Run `{}`? (yes/no) ", shell_command)) {
+        println!("Cancelled.");
+        return;
+    }
+
+    match shell::run_for_context(shell_command) {
+        Ok(block) => {
+            println!("{}", block);
+            let block = if state.strip_ansi { render::strip_ansi(&block) } else { block };
+            state.staged_context.push(block);
+            println!("Output staged; it will be sent with your next message.");
+        }
+        Err(err) => eprintln!("Failed to run command: {}", err),
+    }
+}
+
+// Tokens the conversation would take up if sent now, against the context budget when one is set
+fn show_context_size(state: &ChatState) {
+    let used = trim::log_tokens(&state.model, &state.conversation_log);
+    let budget = match state.context_budget {
+        Some(budget) => budget,
+        None => {
+            println!("{} tokens (no context budget set)", used);
+            return;
+        }
+    };
+    let line = format!("{} / {} tokens ({}%)", used, budget, used * 100 / budget);
+    if used * 10 > budget * 9 {
+        println!("{}", color::warning(&line));
+    } else {
+        println!("{}", line);
+    }
+}
+
+// Attach an image to the next message, for vision-capable models
+fn stage_image(state: &mut ChatState, path: &str) {
+    match images::load(path) {
+        Ok((part, size)) => {
+            state.staged_images.push(part);
+            println!("Attached {} ({} KB); it will be sent with your next message.", path, size.div_ceil(1024));
+        }
+        Err(err) => eprintln!("Can't attach image: {}", err),
+    }
+}
+
+// "<modelA> <modelB> <prompt>": the two model names, then the rest of the line as the prompt,
+// however much whitespace separates them
+fn compare_args(args: &str) -> Option<(&str, &str, &str)> {
+    let (model_a, rest) = args.trim_start().split_once(char::is_whitespace)?;
+    let (model_b, prompt) = rest.trim_start().split_once(char::is_whitespace)?;
+    Some((model_a, model_b, prompt.trim())).filter(|(_, _, prompt)| !prompt.is_empty())
+}
+
+// Ask two models the same prompt with the current context; the conversation itself is left untouched
+async fn compare_models(state: &ChatState, model_a: &str, model_b: &str, prompt: &str) {
+    let mut request_log = state.conversation_log.clone();
+    request_log.push(json!({"role": "user", "content": prompt}));
+
+    // The same settings a chat turn would use, so the comparison reflects real replies
+    let options = state.request_options();
+    let mut spinner = Spinner::start();
+    // Sequential rather than concurrent, since only one request may be in flight at a time
+    let response_a = query_gpt_with(&request_log, state.provider, model_a, state.verbose, &options).await;
+    let response_b = query_gpt_with(&request_log, state.provider, model_b, state.verbose, &options).await;
+    spinner.stop().await;
+
+    for (model, response) in [(model_a, response_a), (model_b, response_b)] {
+        println!("=== {} ===", model);
+        match response {
+            Ok((text, _)) => println!("{}", text.trim_end()),
+            Err(err) => eprintln!("Request failed: {}", err),
+        }
+        println!();
+    }
+}
+
+// Every message with its index, as used by /replay-turn and in search results
+fn show_history(state: &ChatState) {
+    for (index, message) in state.conversation_log.iter().enumerate() {
+        let content = images::text_of(&message["content"]);
+        let first_line = content.lines().next().unwrap_or_default();
+        let mut preview: String = first_line.chars().take(72).collect();
+        if preview.len() < content.trim_end().len() {
+            preview.push('…');
+        }
+        println!("#{:<4} {:<9} {}", index, message["role"].as_str().unwrap_or("unknown"), preview);
+    }
+}
+
+// Print a stable reference to a message, optionally copying it to the clipboard
+fn reference_message(state: &ChatState, index: usize, copy: bool) {
+    let message = match state.conversation_log.get(index) {
+        Some(message) => message,
+        None => {
+            println!("There is no message #{}; see /history.", index);
+            return;
+        }
+    };
+    let reference = refs::make_ref(state.session_name.as_deref(), index, message);
+    println!("{}", reference);
+    if state.session_name.is_none() {
+        println!("(This conversation isn't saved, so the reference only works until you exit.)");
+    }
+    if copy {
+        match refs::copy_to_clipboard(&reference) {
+            Ok(tool) => println!("Copied to the clipboard with {}.", tool),
+            Err(err) => eprintln!("Failed to copy: {}", err),
+        }
+    }
+}
+
+// Show the message a reference points to, from this conversation or the saved session it names
+fn goto_reference(state: &ChatState, reference: &str) {
+    let parsed = match refs::parse_ref(reference) {
+        Some(parsed) => parsed,
+        None => {
+            println!("Not a message reference: {} (expected session@index#hash)", reference);
+            return;
+        }
+    };
+    let stored;
+    let log = if parsed.session == state.session_name {
+        &state.conversation_log
+    } else {
+        let name = parsed.session.as_deref().unwrap_or_default();
+        stored = match storage::open_store().and_then(|store| store.load(name)) {
+            Ok(Some(messages)) => messages,
+            Ok(None) => {
+                println!("No saved session named '{}'.", name);
+                return;
+            }
+            Err(err) => {
+                eprintln!("Failed to load session '{}': {}", name, err);
+                return;
+            }
+        };
+        &stored
+    };
+    let message = match log.get(parsed.index) {
+        Some(message) => message,
+        None => {
+            println!("That session has no message #{}.", parsed.index);
+            return;
+        }
+    };
+    if !refs::matches(&parsed, message) {
+        println!("Warning: this message has changed since the reference was made.");
+    }
+    println!("#{} {}:", parsed.index, message["role"].as_str().unwrap_or("unknown"));
+    println!("{}", images::text_of(&message["content"]));
+}
+
+// Re-send a past user message with the context that preceded it, and show the current
+// model's answer next to the original. The stored log is left untouched.
+async fn replay_turn(state: &ChatState, index: usize) {
+    match state.conversation_log.get(index) {
+        Some(message) if message["role"] == "user" => {}
+        Some(_) => {
+            println!("Message #{} isn't a user message; see /history.", index);
+            return;
+        }
+        None => {
+            println!("There is no message #{}; see /history.", index);
+            return;
+        }
+    }
+    let request_log = &state.conversation_log[..=index];
+    let original = state.conversation_log[index + 1..].iter().find(|message| message["role"] != "system");
+
+    let mut spinner = Spinner::start();
+    let result = query_gpt_with(request_log, state.provider, &state.model, state.verbose, &state.request_options()).await;
+    spinner.stop().await;
+
+    let original = match original.filter(|message| message["role"] == "assistant") {
+        Some(message) => images::text_of(&message["content"]).trim_end().to_string(),
+        None => "(no answer was stored)".to_string(),
+    };
+    let replayed = match result {
+        Ok((text, _)) => text.trim_end().to_string(),
+        Err(err) => format!("(request failed: {})", err),
+    };
+    println!("{}", render::side_by_side(("original", &original), (&state.model, &replayed), terminal_width()));
+}
+
+const CONTINUE_PROMPT: &str = "Continue your previous response exactly where it stopped. Do not repeat any of it and do not add any preamble.";
+
+// Ask the model to pick up the last assistant message where it stopped, extending that message in place
+async fn continue_response(state: &mut ChatState) {
+    let index = match state.conversation_log.iter().rposition(|message| message["role"] == "assistant") {
+        Some(index) => index,
+        None => {
+            println!("Nothing to continue: there is no assistant response yet.");
+            return;
+        }
+    };
+
+    let mut messages = state.conversation_log[..=index].to_vec();
+    messages.push(json!({"role": "user", "content": CONTINUE_PROMPT}));
+    // Recall looks for what the response was answering, not the instruction to go on
+    let query = state.conversation_log[..index].iter().rev().find(|message| message["role"] == "user").map(|message| images::text_of(&message["content"]).into_owned()).unwrap_or_default();
+    let extras = request_extras(state, &query).await;
+    let request_log = build_request(state, &messages, &extras);
+
+    match fetch_response(state, &state.model, &request_log).await {
+        Ok(continuation) if !is_blank_response(&continuation) => {
+            let existing = state.conversation_log[index]["content"].as_str().unwrap_or_default().to_string();
+            let joined = state.stored_content(&join_continuation(&existing, &continuation));
+            state.conversation_log[index]["content"] = json!(joined);
+        }
+        Ok(_) => eprintln!("The model returned an empty continuation; nothing was changed."),
+        Err(err) => eprintln!("Continuation failed: {}", err),
+    }
+}
+
+// Append a continuation to a truncated message. Models often restate the last few words
+// before carrying on, so any overlap between the end of the existing text and the start of
+// the continuation is dropped. The text is otherwise joined as-is, since the cut may have
+// landed mid-word or mid-sentence.
+fn join_continuation(existing: &str, continuation: &str) -> String {
+    // Short overlaps are too likely to be coincidence (e.g. a repeated "the ")
+    const MIN_OVERLAP: usize = 8;
+    let max_overlap = existing.len().min(continuation.len());
+    let overlap = (MIN_OVERLAP..=max_overlap)
+        .rev()
+        .filter(|&len| existing.is_char_boundary(existing.len() - len) && continuation.is_char_boundary(len))
+        .find(|&len| existing[existing.len() - len..] == continuation[..len])
+        .unwrap_or(0);
+    format!("{}{}", existing, &continuation[overlap..])
+}
+
+const IMPROVE_PROMPT_INSTRUCTION: &str = "You are an expert at writing system prompts for chat assistants. Rewrite the system prompt you are given so it better achieves the stated goal, keeping anything that still serves it. Reply with the improved system prompt only, with no commentary.";
+
+// Ask the model for a better system prompt and swap it in if the user accepts. The request
+// is separate from the conversation, which only changes if the suggestion is accepted.
+async fn improve_system_prompt(state: &mut ChatState, goal: &str) {
+    if goal.is_empty() {
+        println!("Usage: /improve-prompt <goal for the assistant>");
+        return;
+    }
+    let position = state.conversation_log.iter().position(|message| message["role"] == "system");
+    let current = position.and_then(|index| state.conversation_log[index]["content"].as_str()).unwrap_or_default().to_string();
+    let request = vec![
+        json!({"role": "system", "content": IMPROVE_PROMPT_INSTRUCTION}),
+        json!({"role": "user", "content": format!("Goal: {}\n\nCurrent system prompt:\n{}", goal, if current.is_empty() { "(none)" } else { &current })}),
+    ];
+
+    let mut spinner = Spinner::start();
+    let result = query_gpt(&request, state.provider, &state.model, state.verbose).await;
+    spinner.stop().await;
+
+    let suggestion = match result {
+        Ok(text) if !is_blank_response(&text) => text.trim().to_string(),
+        Ok(_) => {
+            println!("The model returned an empty suggestion.");
+            return;
+        }
+        Err(err) => {
+            eprintln!("Failed to get a suggestion: {}", err);
+            return;
+        }
+    };
+
+    println!("Suggested system prompt:\n{}\n", suggestion);
+    if !confirm("Use this system prompt? (yes/no) ") {
+        println!("Kept the current system prompt.");
+        return;
+    }
+    state.set_system_prompt(&suggestion);
+    println!("System prompt updated.");
+}
+
+const MAX_STABILITY_RUNS: usize = 20;
+const DEFAULT_STABILITY_INTERVAL_MS: u64 = 1000;
+
+// Send the current context several times and report how alike the responses are. A trailing
+// assistant reply is left out so each run answers the last user message afresh. Runs are spaced
+// by STABILITY_INTERVAL_MS to stay clear of rate limits; the conversation is not changed.
+async fn stability_check(state: &ChatState, runs: usize) {
+    let mut request_log = state.conversation_log.clone();
+    if request_log.last().is_some_and(|message| message["role"] == "assistant") {
+        request_log.pop();
+    }
+    if !request_log.iter().any(|message| message["role"] == "user") {
+        println!("Nothing to send yet: the conversation has no user message.");
+        return;
+    }
+    let options = state.request_options();
+    let interval = env::var("STABILITY_INTERVAL_MS").ok().and_then(|ms| ms.parse().ok()).unwrap_or(DEFAULT_STABILITY_INTERVAL_MS);
+
+    let mut responses = Vec::new();
+    for run in 1..=runs {
+        if run > 1 {
+            tokio::time::sleep(Duration::from_millis(interval)).await;
+        }
+        let mut spinner = Spinner::start();
+        let result = query_gpt_with(&request_log, state.provider, &state.model, state.verbose, &options).await;
+        spinner.stop().await;
+        match result {
+            Ok((text, _)) => {
+                println!("Run {}/{}: {} chars", run, runs, text.chars().count());
+                responses.push(text);
+            }
+            Err(err) => {
+                eprintln!("Run {}/{} failed: {}", run, runs, err);
+                break;
+            }
+        }
+    }
+    if responses.len() < 2 {
+        println!("Not enough responses to compare.");
+        return;
+    }
+
+    let mut scores = Vec::new();
+    for (i, a) in responses.iter().enumerate() {
+        for b in &responses[i + 1..] {
+            scores.push(diff::similarity(a, b));
+        }
+    }
+    let min = scores.iter().cloned().fold(f32::MAX, f32::min);
+    let max = scores.iter().cloned().fold(f32::MIN, f32::max);
+    let avg = scores.iter().sum::<f32>() / scores.len() as f32;
+    println!("Similarity across {} responses ({} pairs): min {:.3} / avg {:.3} / max {:.3}", responses.len(), scores.len(), min, avg, max);
+}
+
+// Added to the temperature for a regenerated response, so it is less likely to repeat itself
+const REGENERATE_TEMPERATURE_BUMP: f64 = 0.2;
+
+// Replace the last response with a fresh attempt. The old one is put back if the new request
+// fails or comes back empty.
+async fn regenerate(state: &mut ChatState) {
+    if state.conversation_log.last().map(|message| message["role"] != "assistant").unwrap_or(true) {
+        println!("Nothing to regenerate: the last message isn't an assistant response.");
+        return;
+    }
+    let previous = state.conversation_log.pop().unwrap_or_default();
+    let request_log = regenerate_request(state).await;
+
+    let temperature = state.temperature;
+    state.temperature = (temperature + REGENERATE_TEMPERATURE_BUMP).min(2.0);
+    let result = fetch_response(state, &state.model, &request_log).await;
+    state.temperature = temperature;
+
+    match result {
+        Ok(response) if !is_blank_response(&response) => state.push_assistant(&response),
+        Ok(_) => {
+            eprintln!("The model returned an empty response; keeping the previous one.");
+            state.conversation_log.push(previous);
+        }
+        Err(err) => {
+            eprintln!("Regeneration failed: {}", err);
+            state.conversation_log.push(previous);
+        }
+    }
+}
+
+// The request a regenerated response answers, built the same way as the turn it replaces
+async fn regenerate_request(state: &mut ChatState) -> Vec<Value> {
+    let query = state.conversation_log.last().filter(|message| message["role"] == "user").map(|message| images::text_of(&message["content"]).into_owned()).unwrap_or_default();
+    let extras = request_extras(state, &query).await;
+    build_request(state, &state.conversation_log, &extras)
+}
+
+// Regenerate the last response, show how it changed, and keep whichever version the user picks
+async fn regenerate_with_diff(state: &mut ChatState) {
+    if state.conversation_log.last().map(|message| message["role"] != "assistant").unwrap_or(true) {
+        println!("Nothing to regenerate: the last message isn't an assistant response.");
+        return;
+    }
+    let previous = state.conversation_log.pop().unwrap_or_default();
+    let old_text = previous["content"].as_str().unwrap_or_default().to_string();
+    let request_log = regenerate_request(state).await;
+
+    let temperature = state.temperature;
+    state.temperature = (temperature + REGENERATE_TEMPERATURE_BUMP).min(2.0);
+    let options = state.request_options();
+    state.temperature = temperature;
+    let mut spinner = Spinner::start();
+    let result = query_gpt_with(&request_log, state.provider, &state.model, state.verbose, &options).await;
+    spinner.stop().await;
+
+    let new_text = match result {
+        Ok((text, _)) => state.apply_transforms(&text),
+        Err(err) => {
+            eprintln!("Regeneration failed: {}", err);
+            state.conversation_log.push(previous);
+            return;
+        }
+    };
+    // An empty reply is never offered, so it can't replace the old one
+    if is_blank_response(&new_text) {
+        eprintln!("The model returned an empty response; keeping the previous one.");
+        state.conversation_log.push(previous);
+        return;
+    }
+
+    println!("{}", diff::word_diff(&old_text, &new_text, color::stdout_enabled()));
+    print!("Keep [o]ld or [n]ew response? ");
+    let _ = io::stdout().flush();
+    let mut choice = String::new();
+    let _ = io::stdin().read_line(&mut choice);
+
+    if choice.trim().eq_ignore_ascii_case("n") || choice.trim().eq_ignore_ascii_case("new") {
+        state.push_assistant(&new_text);
+        println!("Kept the new response.");
+    } else {
+        state.conversation_log.push(previous);
+        println!("Kept the old response.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_args_allow_any_spacing() {
+        assert_eq!(compare_args(" gpt-4o gpt-4o-mini hi there"), Some(("gpt-4o", "gpt-4o-mini", "hi there")));
+        assert_eq!(compare_args("  gpt-4o \t gpt-4o-mini   hi  "), Some(("gpt-4o", "gpt-4o-mini", "hi")));
+        assert_eq!(compare_args(" gpt-4o gpt-4o-mini"), None);
+        assert_eq!(compare_args(" gpt-4o gpt-4o-mini   "), None);
+    }
+
+    #[test]
+    fn continuation_overlap_is_dropped() {
+        let joined = join_continuation("The quick brown fox jumps over", "fox jumps over the lazy dog.");
+        assert_eq!(joined, "The quick brown fox jumps over the lazy dog.");
+    }
+
+    #[test]
+    fn continuation_without_overlap_is_appended() {
+        assert_eq!(join_continuation("First part of the answer.", " Second part."), "First part of the answer. Second part.");
+    }
+
+    #[test]
+    fn short_coincidental_overlap_is_kept() {
+        assert_eq!(join_continuation("over the", " the end"), "over the the end");
+    }
+
+    #[test]
+    fn continuation_mid_word_is_joined_as_is() {
+        assert_eq!(join_continuation("This sentence was cut in the midd", "le of a word."), "This sentence was cut in the middle of a word.");
+    }
+
+    #[test]
+    fn multibyte_characters_at_the_boundary() {
+        assert_eq!(join_continuation("The price is 5€ per item and", "€ per item and then some."), "The price is 5€ per item and then some.");
+        assert_eq!(join_continuation("日本語のテキスト", "です。続きます"), "日本語のテキストです。続きます");
+        // Candidate overlaps that would split a character are skipped rather than sliced
+        assert_eq!(join_continuation("αβγδεζηθ", "ζηθικλμν"), "αβγδεζηθζηθικλμν");
+        assert_eq!(join_continuation("Greek: αβγδεζηθ", "γδεζηθ and more"), "Greek: αβγδεζηθ and more");
+    }
+}
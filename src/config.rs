@@ -0,0 +1,108 @@
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::io;
+
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+pub const DEFAULT_SYSTEM_PROMPT_PATH: &str = "system_prompts/prompt.md";
+
+// Startup settings from config.toml (or CONFIG_PATH). Every field is optional: unset ones keep
+// their built-in defaults, and environment variables override the file field by field.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub model: Option<String>,
+    pub temperature: Option<f64>,
+    pub max_tokens: Option<u64>,
+    // Estimated tokens of history sent with each request; older turns beyond it are left out
+    pub context_budget: Option<usize>,
+    // When set, the startup question about verbose logging is skipped
+    pub verbose: Option<bool>,
+    pub typing_speed_ms: Option<u64>,
+    // A file, a directory or a glob like system_prompts/*.md; several files are joined in order
+    pub system_prompt_path: Option<String>,
+    // Keep a transcript of each session under logs/, as --log does
+    pub transcript: Option<bool>,
+    // Ask for JSON object responses, as --json does. Only for models that support it.
+    pub json_mode: Option<bool>,
+    // Stop sequences for chat turns, e.g. stop = ["###"]
+    pub stop: Option<Vec<String>>,
+    // Continue from the last session at startup, as --resume does
+    pub resume: Option<bool>,
+    // Proxy URL for every request, used instead of HTTPS_PROXY / HTTP_PROXY
+    pub proxy: Option<String>,
+}
+
+impl Config {
+    // A missing file is the same as an empty one; an unreadable or invalid one is reported
+    // and ignored rather than stopping the chat from starting
+    pub fn load() -> Config {
+        let path = env::var("CONFIG_PATH").unwrap_or_else(|_| DEFAULT_CONFIG_PATH.to_string());
+        let mut config = match fs::read_to_string(&path) {
+            Ok(data) => toml::from_str(&data).unwrap_or_else(|err| {
+                eprintln!("Ignoring {}: {}", path, err);
+                Config::default()
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Config::default(),
+            Err(err) => {
+                eprintln!("Failed to read {}: {}", path, err);
+                Config::default()
+            }
+        };
+        config.apply_env();
+        config
+    }
+
+    // OPENAI_MODEL, TEMPERATURE, MAX_TOKENS, CONTEXT_BUDGET, VERBOSE, TYPING_SPEED_MS,
+    // SYSTEM_PROMPT_PATH, TRANSCRIPT, JSON_MODE and RESUME take precedence
+    fn apply_env(&mut self) {
+        if let Ok(model) = env::var("OPENAI_MODEL") {
+            self.model = Some(model);
+        }
+        if let Some(temperature) = env_parsed("TEMPERATURE") {
+            self.temperature = Some(temperature);
+        }
+        if let Some(max_tokens) = env_parsed("MAX_TOKENS") {
+            self.max_tokens = Some(max_tokens);
+        }
+        if let Some(budget) = env_parsed("CONTEXT_BUDGET") {
+            self.context_budget = Some(budget);
+        }
+        if let Ok(verbose) = env::var("VERBOSE") {
+            self.verbose = Some(env_flag(&verbose));
+        }
+        if let Some(ms) = env_parsed("TYPING_SPEED_MS") {
+            self.typing_speed_ms = Some(ms);
+        }
+        if let Ok(path) = env::var("SYSTEM_PROMPT_PATH") {
+            self.system_prompt_path = Some(path);
+        }
+        if let Ok(transcript) = env::var("TRANSCRIPT") {
+            self.transcript = Some(env_flag(&transcript));
+        }
+        if let Ok(json_mode) = env::var("JSON_MODE") {
+            self.json_mode = Some(env_flag(&json_mode));
+        }
+        if let Ok(resume) = env::var("RESUME") {
+            self.resume = Some(env_flag(&resume));
+        }
+    }
+
+    pub fn system_prompt_path(&self) -> &str {
+        self.system_prompt_path.as_deref().unwrap_or(DEFAULT_SYSTEM_PROMPT_PATH)
+    }
+}
+
+fn env_flag(value: &str) -> bool {
+    value == "1" || value.eq_ignore_ascii_case("true") || value.eq_ignore_ascii_case("yes")
+}
+
+// An unparseable value is reported and left to the file or default
+fn env_parsed<T: std::str::FromStr>(name: &str) -> Option<T> {
+    let value = env::var(name).ok()?;
+    let parsed = value.trim().parse().ok();
+    if parsed.is_none() {
+        eprintln!("Ignoring {}: '{}' is not a valid value", name, value);
+    }
+    parsed
+}
@@ -0,0 +1,53 @@
+use difflib::sequencematcher::SequenceMatcher;
+
+const RED_STRIKE: &str = "\x1b[31m\x1b[9m";
+const GREEN: &str = "\x1b[32m";
+const RESET: &str = "\x1b[0m";
+
+// Split text into word and whitespace tokens so a diff can be rejoined losslessly
+pub fn tokenize(text: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut in_space = None;
+    for (index, c) in text.char_indices() {
+        let is_space = c.is_whitespace();
+        if in_space.is_some_and(|previous| previous != is_space) {
+            tokens.push(&text[start..index]);
+            start = index;
+        }
+        in_space = Some(is_space);
+    }
+    if start < text.len() {
+        tokens.push(&text[start..]);
+    }
+    tokens
+}
+
+// Token-level diff of two texts. Styled, removals are struck through in red and additions are
+// green; otherwise they are marked [-like this-] and {+like this+}.
+pub fn word_diff(old: &str, new: &str, styled: bool) -> String {
+    let old_tokens = tokenize(old);
+    let new_tokens = tokenize(new);
+    let mut matcher = SequenceMatcher::new(&old_tokens, &new_tokens);
+    let (removed_start, removed_end, added_start, added_end) = if styled { (RED_STRIKE, RESET, GREEN, RESET) } else { ("[-", "-]", "{+", "+}") };
+
+    let mut out = String::new();
+    for opcode in matcher.get_opcodes() {
+        let removed = old_tokens[opcode.first_start..opcode.first_end].concat();
+        let added = new_tokens[opcode.second_start..opcode.second_end].concat();
+        match opcode.tag.as_str() {
+            "equal" => out.push_str(&removed),
+            "delete" => out.push_str(&format!("{}{}{}", removed_start, removed, removed_end)),
+            "insert" => out.push_str(&format!("{}{}{}", added_start, added, added_end)),
+            _ => out.push_str(&format!("{}{}{}{}{}{}", removed_start, removed, removed_end, added_start, added, added_end)),
+        }
+    }
+    out
+}
+
+// How alike two texts are, from 0.0 (nothing shared) to 1.0 (identical), compared by character
+pub fn similarity(a: &str, b: &str) -> f32 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    SequenceMatcher::new(&a, &b).ratio()
+}
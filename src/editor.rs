@@ -0,0 +1,66 @@
+use crate::files;
+use crate::images;
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+// Editor command from $VISUAL or $EDITOR, falling back to a platform default
+fn editor_command() -> String {
+    env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| if cfg!(windows) { "notepad".to_string() } else { "vi".to_string() })
+}
+
+// Open a file in the user's editor and wait for it to exit
+pub fn open_in_editor(path: &Path) -> Result<(), io::Error> {
+    let editor = editor_command();
+    // Allow editors configured with arguments, e.g. EDITOR="code --wait"
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().unwrap_or("vi");
+    let status = Command::new(program).args(parts).arg(path).status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("{} exited with {}", program, status)))
+    }
+}
+
+// Check that edited content is still a list of chat messages before it replaces the session
+pub fn parse_conversation(data: &str) -> Result<Vec<Value>, String> {
+    let value: Value = serde_json::from_str(data).map_err(|err| format!("invalid JSON: {}", err))?;
+    let messages = value.as_array().ok_or("expected a JSON array of messages")?;
+    for (index, message) in messages.iter().enumerate() {
+        match message["role"].as_str() {
+            Some("system") | Some("user") | Some("assistant") => {}
+            _ => return Err(format!("message {} has a missing or unknown role", index)),
+        }
+        if !images::is_content(&message["content"]) {
+            return Err(format!("message {} has no text or image content", index));
+        }
+    }
+    Ok(messages.clone())
+}
+
+// Dump the conversation to a temp file, let the user edit it, and return the edited log.
+// Returns None when the file was left untouched.
+pub fn edit_conversation(conversation_log: &[Value]) -> Result<Option<Vec<Value>>, Box<dyn std::error::Error>> {
+    let original = serde_json::to_string_pretty(conversation_log)?;
+    let path = files::write_private(&env::temp_dir(), "rustchat", "json", &original)?;
+
+    let result = open_in_editor(&path).and_then(|_| fs::read_to_string(&path));
+    let _ = fs::remove_file(&path);
+    let edited = result?;
+
+    if edited.trim() == original.trim() {
+        return Ok(None);
+    }
+
+    let messages = parse_conversation(&edited)
+        .map_err(|err| io::Error::other(format!("{}; conversation left unchanged", err)))?;
+    Ok(Some(messages))
+}
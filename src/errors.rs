@@ -0,0 +1,98 @@
+use crate::provider::Provider;
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+// The request didn't fit in the model's context window. Kept distinct from other API failures
+// so the chat loop can recover instead of giving up.
+#[derive(Debug)]
+pub struct ContextOverflow {
+    pub detail: String,
+}
+
+impl fmt::Display for ContextOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the conversation is too long for this model's context window ({}). Use /compress to summarize older turns, or switch to a larger-context model with /model",
+            self.detail
+        )
+    }
+}
+
+impl Error for ContextOverflow {}
+
+// Providers word this differently; OpenAI-compatible APIs send a code, others only a message
+fn is_context_overflow(code: &str, message: &str) -> bool {
+    let message = message.to_ascii_lowercase();
+    code == "context_length_exceeded"
+        || ["maximum context length", "context length", "context window", "prompt is too long"].iter().any(|phrase| message.contains(phrase))
+}
+
+// A failed API call, by what went wrong. Each kind's message says what can be done about it.
+#[derive(Debug)]
+pub enum ChatError {
+    // 401/403: the key is missing, wrong or lacks access. Holds the variable the key comes from.
+    Unauthorized { key_var: Option<&'static str>, message: String },
+    // 429, still failing after the automatic retries
+    RateLimited(String),
+    // 400, 404 or 422: the request itself was rejected (bad parameter, unknown model, ...)
+    BadRequest(String),
+    // 5xx, still failing after the automatic retries
+    Server(u16, String),
+    // Any other status
+    Status(u16, String),
+    // No response at all: connection refused, DNS failure, timeout
+    Network(String),
+}
+
+impl fmt::Display for ChatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChatError::Unauthorized { key_var: Some(var), message } => write!(f, "the API rejected the credentials ({}). Check {}", message, var),
+            ChatError::Unauthorized { key_var: None, message } => write!(f, "the API rejected the credentials ({})", message),
+            ChatError::RateLimited(message) => write!(f, "rate limited ({}). Wait a moment before trying again; API_MAX_RETRIES sets how many automatic retries are made", message),
+            ChatError::BadRequest(message) => write!(f, "the API rejected the request ({}). Check the model name and request settings in /config", message),
+            ChatError::Server(status, message) => write!(f, "the API had a server error ({}: {}). Try again later", status, message),
+            ChatError::Status(status, message) => write!(f, "API call failed ({}: {})", status, message),
+            ChatError::Network(message) => write!(f, "couldn't reach the API ({}). Check your connection and the provider's base URL", message),
+        }
+    }
+}
+
+impl Error for ChatError {}
+
+// Error for a failed API response, from its status and the `error.message` in its body.
+// Context overflows are told apart from other bad requests, since the chat can recover from them.
+pub fn api_failure(provider: Provider, status: StatusCode, body: &str) -> Box<dyn Error> {
+    let parsed: Value = serde_json::from_str(body).unwrap_or(Value::Null);
+    let error = &parsed["error"];
+    let message = error["message"].as_str().or_else(|| error.as_str()).unwrap_or(body).trim().to_string();
+    if is_context_overflow(error["code"].as_str().unwrap_or_default(), &message) {
+        return Box::new(ContextOverflow { detail: message });
+    }
+    let message = if message.is_empty() { status.canonical_reason().unwrap_or("no details").to_string() } else { message };
+    Box::new(match status.as_u16() {
+        401 | 403 => ChatError::Unauthorized { key_var: provider.api_key_var(), message },
+        429 => ChatError::RateLimited(message),
+        400 | 404 | 422 => ChatError::BadRequest(message),
+        code @ 500..=599 => ChatError::Server(code, message),
+        code => ChatError::Status(code, message),
+    })
+}
+
+// A request that got no response. Errors that already explain themselves pass through.
+pub fn network_failure(err: Box<dyn Error>) -> Box<dyn Error> {
+    if err.is::<reqwest::Error>() || err.downcast_ref::<io::Error>().is_some_and(|err| err.kind() == io::ErrorKind::TimedOut) {
+        return Box::new(ChatError::Network(err.to_string()));
+    }
+    err
+}
+
+// CONTEXT_OVERFLOW=compress summarizes older turns and retries once instead of just explaining
+pub fn compress_on_overflow() -> bool {
+    env::var("CONTEXT_OVERFLOW").map(|value| value.eq_ignore_ascii_case("compress")).unwrap_or(false)
+}
@@ -0,0 +1,51 @@
+use crate::http;
+use crate::images;
+use chrono::Local;
+use serde_json::{json, Value};
+use std::env;
+use std::io;
+
+const GISTS_URL: &str = "https://api.github.com/gists";
+
+// The conversation as a Markdown document, one section per message
+pub fn to_markdown(conversation_log: &[Value]) -> String {
+    let mut out = format!("# Conversation\n\n_Exported {}_\n", Local::now().format("%Y-%m-%d %H:%M"));
+    for message in conversation_log {
+        let heading = match message["role"].as_str() {
+            Some("system") => "System",
+            Some("user") => "You",
+            Some("assistant") => "Bot",
+            Some(other) => other,
+            None => "Unknown",
+        };
+        out.push_str(&format!("\n## {}\n\n{}\n", heading, images::text_of(&message["content"]).trim_end()));
+    }
+    out
+}
+
+// Upload Markdown as a gist using GITHUB_TOKEN, returning its URL. Private gists are
+// unlisted ("secret") ones, reachable only by link.
+pub async fn create_gist(markdown: &str, public: bool) -> Result<String, Box<dyn std::error::Error>> {
+    let token = env::var("GITHUB_TOKEN").ok().filter(|token| !token.trim().is_empty()).ok_or_else(|| io::Error::other("GITHUB_TOKEN is not set; it needs the gist scope"))?;
+    let body = json!({
+        "description": "Conversation exported from the Rust Chatbot",
+        "public": public,
+        "files": {"conversation.md": {"content": markdown}},
+    });
+
+    // GitHub rejects requests without a user agent; the shared client always sends one
+    let request = http::client()
+        .post(GISTS_URL)
+        .header("Authorization", format!("Bearer {}", token))
+        .header("Accept", "application/vnd.github+json")
+        .json(&body);
+    let response = http::limited(request).send().await?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let error_message = response.text().await?;
+        return Err(Box::new(io::Error::other(format!("gist upload failed ({}): {}", status, error_message))));
+    }
+    let res: Value = response.json().await?;
+    res["html_url"].as_str().map(str::to_string).ok_or_else(|| io::Error::other("gist created but no URL was returned").into())
+}
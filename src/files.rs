@@ -0,0 +1,76 @@
+use std::collections::hash_map::RandomState;
+use std::fs::{self, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Set by --safe: every write to disk is confirmed first, showing the target path. The audit
+// log is the one exception, since it is opted into explicitly and written on every request;
+// the crash journal is confirmed once when it is created.
+static SAFE_MODE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_safe_mode(enabled: bool) {
+    SAFE_MODE.store(enabled, Ordering::Relaxed);
+}
+
+// In safe mode, ask before writing to `path`. A declined write is reported as an error so
+// callers surface it the same way as any other failed write.
+pub fn confirm_write(path: &Path) -> Result<(), io::Error> {
+    if !SAFE_MODE.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    print!("Write to {}? (yes/no) ", path.display());
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    if matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes") {
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("write to {} declined", path.display())))
+    }
+}
+
+pub fn write(path: &Path, contents: &str) -> Result<(), io::Error> {
+    confirm_write(path)?;
+    fs::write(path, contents)
+}
+
+// Write a file by writing a sibling temp file and renaming it into place, so a crash
+// mid-write never leaves a truncated file behind
+pub fn write_atomic(path: &Path, contents: &str) -> Result<(), io::Error> {
+    confirm_write(path)?;
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or("file");
+    let temp_path = path.with_file_name(format!(".{}.tmp", file_name));
+    fs::write(&temp_path, contents)?;
+    fs::rename(&temp_path, path)
+}
+
+// Write `contents` to a new file in `dir` under a random name, readable only by the current
+// user. The file is always freshly created, never opened through an existing file or symlink,
+// so a shared directory such as /tmp is safe to use.
+pub fn write_private(dir: &Path, prefix: &str, extension: &str, contents: &str) -> Result<PathBuf, io::Error> {
+    const ATTEMPTS: usize = 8;
+    for _ in 0..ATTEMPTS {
+        // Each RandomState is seeded randomly, which is all an unguessable name needs
+        let token = RandomState::new().build_hasher().finish();
+        let path = dir.join(format!("{}-{:016x}.{}", prefix, token, extension));
+        confirm_write(&path)?;
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        match options.open(&path) {
+            Ok(mut file) => {
+                file.write_all(contents.as_bytes())?;
+                return Ok(path);
+            }
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("couldn't find a free file name in {}", dir.display())))
+}
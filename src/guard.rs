@@ -0,0 +1,23 @@
+use std::env;
+use std::io;
+use tokio::sync::{Mutex, MutexGuard};
+
+// Only one chat request may be in flight at a time, otherwise spinner and response output
+// from overlapping requests interleave on the terminal. Holding the returned guard for the
+// duration of a request enforces that invariant.
+static IN_FLIGHT: Mutex<()> = Mutex::const_new(());
+
+// REQUEST_OVERLAP=reject fails fast instead of waiting for the running request to finish
+fn reject_overlapping() -> bool {
+    env::var("REQUEST_OVERLAP").map(|value| value.eq_ignore_ascii_case("reject")).unwrap_or(false)
+}
+
+pub async fn acquire() -> Result<MutexGuard<'static, ()>, io::Error> {
+    if reject_overlapping() {
+        IN_FLIGHT
+            .try_lock()
+            .map_err(|_| io::Error::other("another request is already in flight"))
+    } else {
+        Ok(IN_FLIGHT.lock().await)
+    }
+}
@@ -0,0 +1,101 @@
+use reqwest::{Client, ClientBuilder, NoProxy, Proxy, RequestBuilder, Url};
+use std::env;
+use std::io;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+// One client for the whole run, so connections (and their TLS sessions) are pooled across
+// requests instead of being rebuilt every turn
+static CLIENT: OnceLock<Client> = OnceLock::new();
+
+const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+pub fn client() -> &'static Client {
+    CLIENT.get_or_init(|| build_client(None, false))
+}
+
+// Build the shared client with the configured proxy before the first request. Later calls, or
+// calls after a request has already been made, have no effect.
+pub fn configure(proxy: Option<&str>, verbose: bool) {
+    let _ = CLIENT.set(build_client(proxy, verbose));
+}
+
+fn build_client(proxy: Option<&str>, verbose: bool) -> Client {
+    let builder = with_proxies(Client::builder().user_agent(USER_AGENT), proxy, verbose);
+    builder.build().unwrap_or_else(|err| {
+        eprintln!("Failed to configure the HTTP client ({}); using defaults", err);
+        Client::new()
+    })
+}
+
+// Proxies are set explicitly rather than left to reqwest's own detection: the `proxy` config
+// setting is used for every request if given, otherwise HTTPS_PROXY and HTTP_PROXY (or their
+// lowercase forms) for their schemes. Hosts in NO_PROXY always go direct.
+fn with_proxies(builder: ClientBuilder, configured: Option<&str>, verbose: bool) -> ClientBuilder {
+    let from_env = |names: [&str; 2]| names.iter().find_map(|name| env::var(name).ok()).filter(|url| !url.trim().is_empty());
+    let proxies: Vec<(&str, String)> = match configured.filter(|url| !url.trim().is_empty()) {
+        Some(url) => vec![("all", url.trim().to_string())],
+        None => vec![("https", from_env(["HTTPS_PROXY", "https_proxy"])), ("http", from_env(["HTTP_PROXY", "http_proxy"]))]
+            .into_iter()
+            .filter_map(|(scheme, url)| Some((scheme, url?.trim().to_string())))
+            .collect(),
+    };
+
+    let mut builder = builder.no_proxy();
+    let mut in_use = Vec::new();
+    for (scheme, url) in proxies {
+        let proxy = match scheme {
+            "https" => Proxy::https(&url),
+            "http" => Proxy::http(&url),
+            _ => Proxy::all(&url),
+        };
+        match proxy {
+            Ok(proxy) => {
+                builder = builder.proxy(proxy.no_proxy(NoProxy::from_env()));
+                in_use.push(format!("{} for {} requests", redact(&url), scheme));
+            }
+            Err(err) => eprintln!("Ignoring proxy {}: {}", redact(&url), err),
+        }
+    }
+    if verbose {
+        if in_use.is_empty() {
+            println!("No proxy in use");
+        } else {
+            println!("Using proxy {}", in_use.join(", "));
+        }
+    }
+    builder
+}
+
+// A proxy URL as safe to print, with any password hidden
+fn redact(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(mut parsed) if parsed.password().is_some() => {
+            let _ = parsed.set_password(Some("***"));
+            parsed.to_string()
+        }
+        _ => url.to_string(),
+    }
+}
+
+const DEFAULT_REQUEST_TIMEOUT_SECS: f64 = 60.0;
+
+// How long to wait for the API to start answering, from REQUEST_TIMEOUT_SECS (60 by default,
+// 0 to wait indefinitely). A streamed body may take longer than this once it has started.
+pub fn request_timeout() -> Option<Duration> {
+    let secs = env::var("REQUEST_TIMEOUT_SECS").ok().and_then(|value| value.trim().parse().ok()).unwrap_or(DEFAULT_REQUEST_TIMEOUT_SECS);
+    Some(secs).filter(|&secs: &f64| secs > 0.0).map(Duration::from_secs_f64)
+}
+
+// Apply the request timeout to a request made outside the chat turn (model list, embeddings,
+// gists). Their bodies are small, so here the limit covers reading the whole response too.
+pub fn limited(request: RequestBuilder) -> RequestBuilder {
+    match request_timeout() {
+        Some(limit) => request.timeout(limit),
+        None => request,
+    }
+}
+
+pub fn timed_out(limit: Duration) -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, format!("no response from the API within {}s (see REQUEST_TIMEOUT_SECS)", limit.as_secs_f64()))
+}
@@ -0,0 +1,78 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::{json, Value};
+use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
+
+// The API refuses larger images
+const MAX_IMAGE_BYTES: u64 = 20 * 1024 * 1024;
+
+// Image types vision models accept, by file extension
+fn mime_type(path: &Path) -> Option<&'static str> {
+    match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        _ => None,
+    }
+}
+
+// Read an image into an `image_url` content part with the file embedded as a data URL.
+// Also returns its size in bytes.
+pub fn load(path: &str) -> Result<(Value, u64), String> {
+    let path = Path::new(path);
+    let mime = mime_type(path).ok_or_else(|| format!("{} is not a supported image (png, jpg, gif or webp)", path.display()))?;
+    let size = fs::metadata(path).map_err(|err| format!("can't read {}: {}", path.display(), err))?.len();
+    if size > MAX_IMAGE_BYTES {
+        return Err(format!("{} is {} MB; images can be at most {} MB", path.display(), size / (1024 * 1024), MAX_IMAGE_BYTES / (1024 * 1024)));
+    }
+    let data = fs::read(path).map_err(|err| format!("can't read {}: {}", path.display(), err))?;
+    let url = format!("data:{};base64,{}", mime, STANDARD.encode(data));
+    Ok((json!({"type": "image_url", "image_url": {"url": url}}), size))
+}
+
+// Message content for a user turn: plain text on its own, or a content array when images
+// are attached
+pub fn user_content(text: String, images: Vec<Value>) -> Value {
+    if images.is_empty() {
+        return json!(text);
+    }
+    let mut parts = vec![json!({"type": "text", "text": text})];
+    parts.extend(images);
+    json!(parts)
+}
+
+// Whether a value is message content this chat can send: text, or an array of text and image
+// parts as `user_content` builds
+pub fn is_content(content: &Value) -> bool {
+    match content {
+        Value::String(_) => true,
+        Value::Array(parts) => parts.iter().all(|part| match part["type"].as_str() {
+            Some("text") => part["text"].is_string(),
+            Some("image_url") => part["image_url"]["url"].is_string(),
+            _ => false,
+        }),
+        _ => false,
+    }
+}
+
+// The text of a message's content, whichever shape it has. Images in a content array are
+// stood in for by "[image]", so previews, exports and token estimates still see the turn.
+pub fn text_of(content: &Value) -> Cow<'_, str> {
+    match content {
+        Value::String(text) => Cow::Borrowed(text),
+        Value::Array(parts) => Cow::Owned(
+            parts
+                .iter()
+                .map(|part| match part["type"].as_str() {
+                    Some("text") => part["text"].as_str().unwrap_or_default().to_string(),
+                    _ => "[image]".to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
+        _ => Cow::Borrowed(""),
+    }
+}
@@ -0,0 +1,219 @@
+use crate::color;
+use crate::secrets;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Config, Context, EditMode, Editor, Helper};
+use std::borrow::Cow;
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+
+const DIM: &str = "\x1b[2m";
+// A line holding just this starts a multi-line message, and the next such line ends it
+pub const BLOCK_DELIMITER: &str = "\"\"\"";
+const RESET: &str = "\x1b[0m";
+
+// Rough token estimate (about four characters per token for English text)
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+// Character, word and estimated-token counts for a piece of input
+pub fn counts_summary(text: &str) -> String {
+    format!("{} chars, {} words, ~{} tokens", text.chars().count(), text.split_whitespace().count(), estimate_tokens(text))
+}
+
+// Shows live counts for the line being typed as a dimmed hint after the cursor
+struct InputHelper {
+    live_counts: bool,
+}
+
+impl Hinter for InputHelper {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<String> {
+        if !self.live_counts || line.is_empty() || pos < line.len() {
+            return None;
+        }
+        Some(format!("  [{}]", counts_summary(line)))
+    }
+}
+
+impl Highlighter for InputHelper {
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(&'s self, prompt: &'p str, _default: bool) -> Cow<'b, str> {
+        Cow::Owned(color::prompt(prompt))
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("{}{}{}", DIM, hint, RESET))
+    }
+}
+
+impl Completer for InputHelper {
+    type Candidate = String;
+}
+
+impl Validator for InputHelper {}
+
+impl Helper for InputHelper {}
+
+// One message from the prompt: a single line, or a block of lines between delimiters
+pub enum Input {
+    Line(String),
+    Block(String),
+}
+
+const HISTORY_SIZE: usize = 1000;
+const DEFAULT_HISTORY_FILE: &str = ".rustchat_history";
+
+// Input history is kept across runs with INPUT_HISTORY=1, in ~/.rustchat_history unless
+// INPUT_HISTORY_PATH says otherwise
+fn history_path() -> Option<PathBuf> {
+    let enabled = env::var("INPUT_HISTORY").map(|value| value == "1" || value.eq_ignore_ascii_case("true")).unwrap_or(false);
+    if !enabled {
+        return None;
+    }
+    if let Ok(path) = env::var("INPUT_HISTORY_PATH") {
+        return Some(PathBuf::from(path));
+    }
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?;
+    Some(PathBuf::from(home).join(DEFAULT_HISTORY_FILE))
+}
+
+// Line editor for the chat prompt, with emacs keybindings and up-arrow history of this
+// session's lines. When stdin is not a terminal it falls back to plain line reads, so piped
+// input keeps working.
+pub struct LineReader {
+    editor: Editor<InputHelper, DefaultHistory>,
+    history_path: Option<PathBuf>,
+}
+
+impl LineReader {
+    pub fn new() -> Result<LineReader, ReadlineError> {
+        let config = Config::builder().edit_mode(EditMode::Emacs).max_history_size(HISTORY_SIZE)?.auto_add_history(false).build();
+        let mut editor = Editor::with_config(config)?;
+        editor.set_helper(Some(InputHelper { live_counts: false }));
+        let history_path = history_path();
+        if let Some(path) = &history_path {
+            // No file yet just means an empty history
+            if let Err(err) = editor.load_history(path) {
+                if !matches!(&err, ReadlineError::Io(err) if err.kind() == io::ErrorKind::NotFound) {
+                    eprintln!("Failed to read input history {}: {}", path.display(), err);
+                }
+            }
+        }
+        Ok(LineReader { editor, history_path })
+    }
+
+    // Remember a line for up-arrow recall. Lines holding an API key are never added, so a
+    // pasted key can't end up in the history file.
+    fn remember(&mut self, line: &str) {
+        if line.trim().is_empty() || secrets::count_keys(line) > 0 {
+            return;
+        }
+        if self.editor.add_history_entry(line).unwrap_or(false) {
+            if let Some(path) = &self.history_path {
+                if let Err(err) = self.editor.append_history(path) {
+                    eprintln!("Failed to save input history {}: {}", path.display(), err);
+                }
+            }
+        }
+    }
+
+    // Read one line from the editor
+    fn read_line(&mut self, prompt: &str, live_counts: bool) -> io::Result<Read> {
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.live_counts = live_counts;
+        }
+        // The editor only draws the prompt on a terminal
+        if !io::stdin().is_terminal() {
+            print!("{}", color::prompt(prompt));
+            io::stdout().flush()?;
+        }
+        match self.editor.readline(prompt) {
+            Ok(line) => Ok(Read::Line(line)),
+            Err(ReadlineError::Interrupted) => Ok(Read::Interrupted),
+            Err(ReadlineError::Eof) => Ok(Read::Closed),
+            Err(ReadlineError::Io(err)) => Err(err),
+            Err(err) => Err(io::Error::other(err)),
+        }
+    }
+
+    // Read one message. A delimiter line switches to multi-line capture, which keeps every line
+    // exactly as typed (indentation, blank lines) until the closing delimiter. Input closed in
+    // the middle of a block sends what was captured so far; Ctrl-C there throws the block away
+    // and prompts again. Returns None when input is closed (EOF or Ctrl-C/Ctrl-D) at the prompt.
+    pub fn read_message(&mut self, prompt: &str, live_counts: bool) -> io::Result<Option<Input>> {
+        loop {
+            let line = match self.read_line(prompt, live_counts)? {
+                Read::Line(line) => line,
+                Read::Interrupted | Read::Closed => return Ok(None),
+            };
+            if line.trim() != BLOCK_DELIMITER {
+                // Lines of a block aren't worth recalling on their own, so only single lines are kept
+                self.remember(&line);
+                return Ok(Some(Input::Line(line)));
+            }
+            match collect_block(|| self.read_line("... ", false))? {
+                Some(block) => return Ok(Some(Input::Block(block))),
+                None => println!("(Block discarded)"),
+            }
+        }
+    }
+}
+
+// What a single prompt returned
+enum Read {
+    Line(String),
+    // Ctrl-C
+    Interrupted,
+    // EOF or Ctrl-D
+    Closed,
+}
+
+// The lines of a block after its opening delimiter, up to the closing one or the end of input.
+// None if Ctrl-C was pressed part way through.
+fn collect_block(mut next: impl FnMut() -> io::Result<Read>) -> io::Result<Option<String>> {
+    let mut lines = Vec::new();
+    loop {
+        match next()? {
+            Read::Line(line) if line.trim() == BLOCK_DELIMITER => break,
+            Read::Line(line) => lines.push(line),
+            Read::Interrupted => return Ok(None),
+            Read::Closed => break,
+        }
+    }
+    Ok(Some(lines.join("\n")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reads(items: Vec<Read>) -> impl FnMut() -> io::Result<Read> {
+        let mut items = items.into_iter();
+        move || Ok(items.next().unwrap_or(Read::Closed))
+    }
+
+    #[test]
+    fn block_ends_at_the_closing_delimiter() {
+        let block = collect_block(reads(vec![Read::Line("  first".into()), Read::Line(String::new()), Read::Line(BLOCK_DELIMITER.into()), Read::Line("after".into())]));
+        assert_eq!(block.unwrap(), Some("  first\n".to_string()));
+    }
+
+    #[test]
+    fn interrupted_block_is_discarded() {
+        let block = collect_block(reads(vec![Read::Line("partial".into()), Read::Interrupted, Read::Line(BLOCK_DELIMITER.into())]));
+        assert_eq!(block.unwrap(), None);
+    }
+
+    #[test]
+    fn closed_input_keeps_the_partial_block() {
+        let block = collect_block(reads(vec![Read::Line("partial".into()), Read::Closed]));
+        assert_eq!(block.unwrap(), Some("partial".to_string()));
+    }
+}
@@ -0,0 +1,71 @@
+// Single keypresses read while output is being printed, e.g. space to pause the typewriter.
+// Only Unix terminals are supported; everywhere else no keys are ever reported.
+#[cfg(unix)]
+use std::io::{self, IsTerminal};
+
+#[cfg(unix)]
+pub struct KeyWatcher {
+    original: libc::termios,
+}
+
+#[cfg(not(unix))]
+pub struct KeyWatcher;
+
+#[cfg(unix)]
+impl KeyWatcher {
+    // Switch the terminal to unbuffered, unechoed input until the watcher is dropped. Output
+    // processing and signal keys (Ctrl-C) are left alone. Returns None when stdin or stdout
+    // is not a terminal.
+    pub fn start() -> Option<KeyWatcher> {
+        if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+            return None;
+        }
+        // SAFETY: termios is plain data and tcgetattr/tcsetattr only touch the struct given
+        let mut settings: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut settings) } != 0 {
+            return None;
+        }
+        let original = settings;
+        settings.c_lflag &= !(libc::ICANON | libc::ECHO);
+        // Reads return immediately, with or without a key
+        settings.c_cc[libc::VMIN] = 0;
+        settings.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &settings) } != 0 {
+            return None;
+        }
+        Some(KeyWatcher { original })
+    }
+
+    // The next key pressed since the last call, if any
+    pub fn poll(&self) -> Option<u8> {
+        let mut byte = 0u8;
+        // SAFETY: reads at most one byte into a local buffer
+        let read = unsafe { libc::read(libc::STDIN_FILENO, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+        if read == 1 {
+            Some(byte)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for KeyWatcher {
+    fn drop(&mut self) {
+        // SAFETY: restores the settings captured in start
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+impl KeyWatcher {
+    pub fn start() -> Option<KeyWatcher> {
+        None
+    }
+
+    pub fn poll(&self) -> Option<u8> {
+        None
+    }
+}
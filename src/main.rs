@@ -1,136 +1,1430 @@
+mod audit;
+mod cache;
+mod chat;
+mod color;
+mod commands;
+mod config;
+mod diff;
+mod editor;
+mod errors;
+mod export;
+mod files;
+mod guard;
+mod http;
+mod images;
+mod input;
+mod keys;
+mod memory;
+mod models;
+mod persona;
+mod picker;
+mod profile;
+mod provider;
+mod recall;
+mod refs;
+mod render;
+mod retry;
+mod routing;
+mod schema;
+mod search;
+mod secrets;
+mod shell;
+mod shutdown;
+mod storage;
+mod stream;
+mod summarize;
+mod tokens;
+mod tools;
+mod transcript;
+mod trim;
+mod usage;
+mod wal;
+
+use chat::{ApiBackend, ChatBackend, EchoBackend, RequestOptions};
 use dotenv::dotenv;
-use reqwest::Client;
+use commands::CommandResult;
+use recall::RecallIndex;
+use input::{Input, LineReader};
+use provider::Provider;
+use render::{OutputFormat, Renderer, StreamRenderer};
+use stream::{OutputBuffer, ProgressBar, SseParser};
+use usage::Usage;
+use regex::Regex;
 use serde_json::{json, Value};
 use std::env;
 use std::fs;
-use std::io::{self, Write};
-use tokio;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
 use tokio::sync::oneshot;
 use tokio::time::{sleep, Duration};
 
-// Utility function to read the initial prompt from a file
-fn read_initial_prompt(file_path: &str) -> Result<String, io::Error> {
-    fs::read_to_string(file_path)
+const DEFAULT_MODEL: &str = "gpt-3.5-turbo";
+const DEFAULT_TYPING_DELAY_MS: u64 = 10;
+const DEFAULT_TEMPERATURE: f64 = 1.0;
+
+// Live state of the chat session, shared between the main loop and slash commands
+pub struct ChatState {
+    pub conversation_log: Vec<Value>,
+    // Name of the stored session last saved or loaded, if any
+    pub session_name: Option<String>,
+    pub provider: Provider,
+    pub model: String,
+    // Provider/model pairs tried in order when a request to the active one fails
+    pub fallback_chain: Vec<(Provider, String)>,
+    pub verbose: bool,
+    // How assistant output is displayed; the log always keeps the original text
+    pub format: OutputFormat,
+    // Set once a format is picked with /format. Until then streamed replies are printed raw,
+    // since rendering holds each line back until it is complete
+    pub format_chosen: bool,
+    // Per-character typewriter delay for buffered responses; 0 prints instantly
+    pub typing_delay_ms: u64,
+    // Stream tokens as they arrive instead of waiting for the full response
+    pub stream: bool,
+    // Show character/word/token counts while typing, toggled with /wc
+    pub live_counts: bool,
+    // Semantic recall of saved sessions is opt-in via /recall
+    pub recall_enabled: bool,
+    pub recall_index: RecallIndex,
+    // Named steering notes (e.g. a response suffix) sent with every request but never stored
+    pub addenda: Vec<(String, String)>,
+    // Language code locked in with /lang, shown alongside its addendum
+    pub language: Option<String>,
+    // Context blocks (e.g. command output) waiting to be prepended to the next message
+    pub staged_context: Vec<String>,
+    // Images from /image waiting to be attached to the next message, as content parts
+    pub staged_images: Vec<Value>,
+    // Ad-hoc regex substitutions applied to each new response, set with /transform
+    pub transforms: Vec<(Regex, String)>,
+    // Persona whose prompt is the active system prompt, if one was picked
+    pub persona: Option<String>,
+    // File the system prompt was read from, re-read by /reload
+    pub prompt_path: String,
+    // Append-only log of each message, kept with --log
+    pub transcript: Option<transcript::Transcript>,
+    // Named snapshots of the conversation, saved and loaded along with it
+    pub checkpoints: Vec<(String, Vec<Value>)>,
+    // Crash journal of the conversation, active with WAL=1
+    pub wal: wal::Wal,
+    // Strip trailing whitespace from assistant messages before storing them
+    pub trim_trailing_whitespace: bool,
+    // Strip ANSI escape codes from stored messages, so saved sessions, exports and logs
+    // only ever see clean text; styling is applied at display time
+    pub strip_ansi: bool,
+    // Structured output schema for responses, from RESPONSE_SCHEMA or /schema
+    pub schema: Option<schema::Schema>,
+    // Tell the model the current date and time with every request (INJECT_DATETIME or /datetime)
+    pub inject_datetime: bool,
+    // Ask for a JSON object with each chat turn (--json, json_mode or /json); a schema wins
+    pub json_mode: bool,
+    // Stop sequences sent with each chat turn, from the config or /stop
+    pub stop: Vec<String>,
+    // Alternative replies asked for with each chat turn (/choices); above 1 they are offered
+    // to pick from
+    pub choices: u32,
+    // Sampling temperature and response length cap for chat turns
+    pub temperature: f64,
+    pub max_tokens: Option<u64>,
+    // Show token usage and estimated cost after each response, toggled with /usage
+    pub show_usage: bool,
+    // Estimated token budget for the history sent with a request; the log itself keeps everything
+    pub context_budget: Option<usize>,
+}
+
+impl ChatState {
+    fn new(verbose: bool, model: String, provider: Provider) -> ChatState {
+        ChatState {
+            conversation_log: Vec::new(),
+            session_name: None,
+            provider,
+            model,
+            fallback_chain: provider::fallback_chain(),
+            verbose,
+            // Markdown is styled where the styling can be seen, and left as-is when piped
+            format: if color::stdout_enabled() { OutputFormat::Markdown } else { OutputFormat::Raw },
+            format_chosen: false,
+            typing_delay_ms: DEFAULT_TYPING_DELAY_MS,
+            stream: env::var("OPENAI_STREAM").map(|value| value == "1" || value.eq_ignore_ascii_case("true")).unwrap_or(false),
+            live_counts: false,
+            recall_enabled: false,
+            recall_index: RecallIndex::default(),
+            addenda: Vec::new(),
+            language: None,
+            staged_context: Vec::new(),
+            staged_images: Vec::new(),
+            transforms: Vec::new(),
+            persona: None,
+            checkpoints: Vec::new(),
+            wal: wal::Wal::new(false),
+            trim_trailing_whitespace: env::var("TRIM_TRAILING_WHITESPACE").map(|value| value != "0" && !value.eq_ignore_ascii_case("false")).unwrap_or(true),
+            strip_ansi: env::var("STRIP_ANSI").map(|value| value != "0" && !value.eq_ignore_ascii_case("false")).unwrap_or(true),
+            schema: schema::from_env(),
+            json_mode: false,
+            stop: Vec::new(),
+            choices: 1,
+            inject_datetime: env::var("INJECT_DATETIME").map(|value| value == "1" || value.eq_ignore_ascii_case("true")).unwrap_or(false),
+            temperature: DEFAULT_TEMPERATURE,
+            max_tokens: None,
+            show_usage: usage::show_from_env(),
+            context_budget: None,
+            prompt_path: config::DEFAULT_SYSTEM_PROMPT_PATH.to_string(),
+            transcript: None,
+        }
+    }
+
+    // Assistant content as it should be stored. Leading whitespace is always kept since it
+    // can be significant (e.g. an indented code block).
+    pub fn stored_content(&self, content: &str) -> String {
+        let content = if self.strip_ansi { render::strip_ansi(content) } else { content.to_string() };
+        if self.trim_trailing_whitespace {
+            content.trim_end().to_string()
+        } else {
+            content
+        }
+    }
+
+    // Settings sent with each chat turn
+    pub fn request_options(&self) -> RequestOptions {
+        RequestOptions {
+            temperature: Some(self.temperature),
+            max_tokens: self.max_tokens,
+            response_format: match &self.schema {
+                Some(schema) => Some(schema.response_format()),
+                None if self.json_mode => Some(json!({"type": "json_object"})),
+                None => None,
+            },
+            tools: tools::definitions(),
+            stop: self.stop.clone(),
+            n: Some(self.choices),
+        }
+    }
+
+    // Run a response through the /transform rules, in the order they were added
+    pub fn apply_transforms(&self, response: &str) -> String {
+        self.transforms.iter().fold(response.to_string(), |text, (pattern, replacement)| pattern.replace_all(&text, replacement.as_str()).into_owned())
+    }
+
+    // Replace the system prompt, or add one at the start if there is none
+    pub fn set_system_prompt(&mut self, prompt: &str) {
+        match self.conversation_log.iter_mut().find(|message| message["role"] == "system") {
+            Some(message) => message["content"] = json!(prompt),
+            None => self.conversation_log.insert(0, json!({"role": "system", "content": prompt})),
+        }
+    }
+
+    pub fn push_assistant(&mut self, content: &str) {
+        let content = self.stored_content(content);
+        if let Some(transcript) = &mut self.transcript {
+            transcript.record("assistant", &content);
+        }
+        self.conversation_log.push(json!({"role": "assistant", "content": content}));
+    }
+
+    // Add or replace a named system addendum
+    pub fn set_addendum(&mut self, name: &str, text: String) {
+        match self.addenda.iter_mut().find(|(existing, _)| existing == name) {
+            Some(entry) => entry.1 = text,
+            None => self.addenda.push((name.to_string(), text)),
+        }
+    }
+
+    // Remove a named system addendum, returning whether one was active
+    pub fn clear_addendum(&mut self, name: &str) -> bool {
+        let before = self.addenda.len();
+        self.addenda.retain(|(existing, _)| existing != name);
+        self.addenda.len() != before
+    }
+
+    pub fn addendum(&self, name: &str) -> Option<&str> {
+        self.addenda.iter().find(|(existing, _)| existing == name).map(|(_, text)| text.as_str())
+    }
+
+    // All active addenda combined into one system message
+    fn addenda_message(&self) -> Option<Value> {
+        if self.addenda.is_empty() {
+            return None;
+        }
+        let text = self.addenda.iter().map(|(_, text)| text.as_str()).collect::<Vec<_>>().join("\n");
+        Some(json!({"role": "system", "content": text}))
+    }
+}
+
+// Command-line options; anything unrecognised is reported and ignored
+#[derive(Default)]
+struct Args {
+    init_script: Option<String>,
+    // Non-interactive search across stored sessions
+    search: Option<String>,
+    search_regex: bool,
+    search_case_sensitive: bool,
+    // Confirm every write to disk
+    safe: bool,
+    // Rebuild the conversation from the crash journal
+    recover: bool,
+    // Read stdin as context for a single question, given as the positional argument
+    stdin_as_context: bool,
+    question: Option<String>,
+    // Model to use, overriding OPENAI_MODEL
+    model: Option<String>,
+    // Typewriter delay per character in ms, 0 printing responses instantly
+    typing_speed: Option<u64>,
+    // Sampling settings, overriding the config
+    temperature: Option<f64>,
+    max_tokens: Option<u64>,
+    // Print responses exactly as returned, without Markdown rendering
+    raw: bool,
+    // Keep a transcript of the session under logs/
+    log: bool,
+    // Skip every startup question, for scripted use
+    no_interactive: bool,
+    // Ask for JSON object responses
+    json: bool,
+    // Continue the conversation from the last exit
+    resume: bool,
+    // Persona to start with, overriding PERSONA
+    persona: Option<String>,
+    // Answer locally with an echo instead of calling the API
+    offline: bool,
+}
+
+fn parse_args() -> Args {
+    let mut args = Args::default();
+    let mut iter = env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--init" => args.init_script = iter.next(),
+            "--search" => args.search = iter.next(),
+            "--regex" => args.search_regex = true,
+            "--case-sensitive" => args.search_case_sensitive = true,
+            "--safe" => args.safe = true,
+            "--recover" => args.recover = true,
+            "--stdin-as-context" => args.stdin_as_context = true,
+            "--raw" => args.raw = true,
+            "--log" => args.log = true,
+            "--no-interactive" => args.no_interactive = true,
+            "--json" => args.json = true,
+            "--resume" => args.resume = true,
+            "--persona" => args.persona = iter.next(),
+            "--offline" => args.offline = true,
+            "--model" => args.model = iter.next(),
+            "--typing-speed" => match iter.next().map(|ms| ms.parse::<u64>()) {
+                Some(Ok(ms)) => args.typing_speed = Some(ms),
+                _ => eprintln!("--typing-speed needs a delay in milliseconds"),
+            },
+            "--temperature" => match iter.next().map(|value| value.parse::<f64>()) {
+                Some(Ok(temperature)) => args.temperature = Some(temperature),
+                _ => eprintln!("--temperature needs a number"),
+            },
+            "--max-tokens" => match iter.next().map(|value| value.parse::<u64>()) {
+                Some(Ok(tokens)) => args.max_tokens = Some(tokens),
+                _ => eprintln!("--max-tokens needs a token count"),
+            },
+            other if !other.starts_with('-') && args.question.is_none() => args.question = Some(other.to_string()),
+            other => eprintln!("Ignoring unknown argument: {}", other),
+        }
+    }
+    args
+}
+
+// Model from --model, then the configured one (OPENAI_MODEL or config.toml), then the built-in
+// default. An empty choice would only fail at the first request, so it is reported up front and
+// the default used instead.
+fn choose_model(cli_model: Option<String>, configured: Option<String>) -> String {
+    let (source, model) = match (cli_model, configured) {
+        (Some(model), _) => ("--model", model),
+        (None, Some(model)) => ("the configured model", model),
+        (None, None) => return DEFAULT_MODEL.to_string(),
+    };
+    if model.trim().is_empty() {
+        eprintln!("Warning: {} is empty; using {}", source, DEFAULT_MODEL);
+        return DEFAULT_MODEL.to_string();
+    }
+    model.trim().to_string()
+}
+
+// The API accepts at most this many stop sequences
+pub const MAX_STOP_SEQUENCES: usize = 4;
+
+// Request settings: temperature, max_tokens and JSON mode from the command line, then the
+// config; the context budget and stop sequences from the config only. Out-of-range values are
+// reported and replaced by the defaults rather than sent to fail at the API.
+fn apply_request_settings(state: &mut ChatState, args: &Args, config: &config::Config) {
+    let temperature = args.temperature.or(config.temperature).unwrap_or(DEFAULT_TEMPERATURE);
+    if (0.0..=2.0).contains(&temperature) {
+        state.temperature = temperature;
+    } else {
+        eprintln!("Warning: temperature {} is outside 0-2; using {}", temperature, DEFAULT_TEMPERATURE);
+    }
+    // 0 means no cap
+    state.max_tokens = args.max_tokens.or(config.max_tokens).filter(|&tokens| tokens > 0);
+    state.context_budget = config.context_budget.filter(|&budget| budget > 0);
+    state.json_mode = args.json || config.json_mode == Some(true);
+    state.stop = config.stop.clone().unwrap_or_default();
+    if state.stop.len() > MAX_STOP_SEQUENCES {
+        eprintln!("Warning: only the first {} stop sequences are used", MAX_STOP_SEQUENCES);
+        state.stop.truncate(MAX_STOP_SEQUENCES);
+    }
+}
+
+// Put between the pieces of a prompt split across several files
+const PROMPT_PART_SEPARATOR: &str = "\n\n";
+
+// Recalled snippets and addenda for a request answering `query`. They are only sent with the
+// request, never stored in the log.
+async fn request_extras(state: &mut ChatState, query: &str) -> Vec<Value> {
+    let mut extras = Vec::new();
+    if state.recall_enabled {
+        match recall::recall_context(&state.recall_index, query).await {
+            Ok(Some(context)) => extras.push(context),
+            Ok(None) => {}
+            Err(err) => eprintln!("{}", color::error(&format!("Recall failed: {}", err))),
+        }
+    }
+    // The timestamp goes stale, so it is rewritten for every request
+    if state.inject_datetime {
+        state.set_addendum("datetime", datetime_note());
+    }
+    // JSON mode is rejected unless the messages mention JSON, so it always says what it wants
+    if state.json_mode && state.schema.is_none() {
+        state.set_addendum("json", JSON_MODE_NOTE.to_string());
+    } else {
+        state.clear_addendum("json");
+    }
+    if let Some(addenda) = state.addenda_message() {
+        extras.push(addenda);
+    }
+    extras
+}
+
+// The messages sent for a chat turn: `messages` (normally the conversation) with `extras`
+// (recalled snippets and addenda) just before the last message, and the oldest turns left out
+// once the result outgrows the context budget
+fn build_request(state: &ChatState, messages: &[Value], extras: &[Value]) -> Vec<Value> {
+    let mut request_log = messages.to_vec();
+    let position = request_log.len().saturating_sub(1);
+    request_log.splice(position..position, extras.iter().cloned());
+    if let Some(budget) = state.context_budget {
+        let dropped = trim::trim_to_budget(&mut request_log, budget, &state.model);
+        if dropped > 0 {
+            println!("(Left out {} older messages to fit the {}-token context budget)", dropped, budget);
+        }
+    }
+    request_log
+}
+
+// Utility function to read the initial prompt from a file. The path can also be a directory or
+// a glob such as `system_prompts/*.md`, in which case every matching file is read in name order
+// and the pieces are joined into one prompt.
+pub fn read_initial_prompt(file_path: &str, verbose: bool) -> Result<String, io::Error> {
+    let files = prompt_files(file_path)?;
+    if verbose {
+        let names: Vec<String> = files.iter().map(|file| file.display().to_string()).collect();
+        println!("System prompt loaded from: {}", names.join(", "));
+    }
+    if let [file] = files.as_slice() {
+        return fs::read_to_string(file);
+    }
+    let parts = files.iter().map(fs::read_to_string).collect::<Result<Vec<_>, _>>()?;
+    Ok(parts.iter().map(|part| part.trim_end()).collect::<Vec<_>>().join(PROMPT_PART_SEPARATOR))
+}
+
+// The files a prompt path names. Wildcards (`*` and `?`) are only matched in the last component.
+fn prompt_files(file_path: &str) -> Result<Vec<PathBuf>, io::Error> {
+    let path = Path::new(file_path);
+    let (dir, pattern) = if path.is_dir() {
+        (path, "*".to_string())
+    } else {
+        match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if name.contains(['*', '?']) => (path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new(".")), name.to_string()),
+            _ => return Ok(vec![path.to_path_buf()]),
+        }
+    };
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| file.is_file())
+        .filter(|file| file.file_name().and_then(|name| name.to_str()).is_some_and(|name| !name.starts_with('.') && wildcard_match(&pattern, name)))
+        .collect();
+    if files.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("no prompt files match {}", file_path)));
+    }
+    files.sort();
+    Ok(files)
+}
+
+// Shell-style match of `name` against a pattern where `*` is any run of characters and `?` is
+// any one character
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was, and how much of the name it has swallowed so far
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, swallowed)) => {
+                    p = star + 1;
+                    n = swallowed + 1;
+                    backtrack = Some((star, swallowed + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+const BUILTIN_DEFAULT_PROMPT: &str = "You are a helpful assistant.";
+
+// What to use when the prompt file is empty or missing. EMPTY_PROMPT=default swaps in a
+// helpful-assistant prompt (overridable via DEFAULT_SYSTEM_PROMPT); otherwise the chat runs
+// without a system prompt.
+fn prompt_or_default(prompt: String) -> String {
+    if !prompt.trim().is_empty() {
+        return prompt;
+    }
+    let use_default = env::var("EMPTY_PROMPT").map(|value| value.eq_ignore_ascii_case("default")).unwrap_or(false);
+    if use_default {
+        let default = env::var("DEFAULT_SYSTEM_PROMPT").ok().filter(|text| !text.trim().is_empty());
+        println!("System prompt is empty; using the default prompt.");
+        default.unwrap_or_else(|| BUILTIN_DEFAULT_PROMPT.to_string())
+    } else {
+        println!("System prompt is empty; running without one.");
+        String::new()
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok();
+    let args = parse_args();
+    files::set_safe_mode(args.safe);
+    chat::set_offline(args.offline);
+
+    if let Some(term) = &args.search {
+        let matcher = search::Matcher::new(term, args.search_regex, args.search_case_sensitive)?;
+        let hits = storage::open_store()?.search(&matcher)?;
+        search::print_hits(&hits);
+        return Ok(());
+    }
+
+    let config = config::Config::load();
+
+    // Every request would fail without a key, so stop before the chat starts and say how to fix it
+    let provider = Provider::from_env();
+    if args.offline {
+        println!("Offline mode: replies are echoed locally and nothing is sent to the API.");
+    } else if let Err(err) = provider.api_key() {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+
+    if args.stdin_as_context {
+        http::configure(config.proxy.as_deref(), false);
+        let mut state = ChatState::new(false, choose_model(args.model.clone(), config.model.clone()), provider);
+        apply_request_settings(&mut state, &args, &config);
+        if args.raw {
+            state.format = OutputFormat::Raw;
+        }
+        return answer_with_stdin_context(args.question, &config, state).await;
+    }
 
     println!("Welcome to the Rust Chatbot!");
-    println!("Do you want verbose logging? (yes/no)");
-    let mut verbose_input = String::new();
-    io::stdin().read_line(&mut verbose_input)?;
-    let verbose = verbose_input.trim().eq_ignore_ascii_case("yes");
+    // Only asked when the config doesn't settle it
+    let verbose = match config.verbose {
+        Some(verbose) => verbose,
+        None if args.no_interactive => false,
+        None => {
+            println!("Do you want verbose logging? (yes/no)");
+            let mut verbose_input = String::new();
+            io::stdin().read_line(&mut verbose_input)?;
+            verbose_input.trim().eq_ignore_ascii_case("yes")
+        }
+    };
+    http::configure(config.proxy.as_deref(), verbose);
 
     // Read the initial system prompt from the file
-    let file_prompt = read_initial_prompt("system_prompts/prompt.md").unwrap_or_else(|err| {
+    let file_prompt = read_initial_prompt(config.system_prompt_path(), verbose).unwrap_or_else(|err| {
         eprintln!("Failed to read initial prompt from file: {}", err);
-        String::new() // Fallback to an empty string or provide a default prompt
+        String::new()
     });
+    let file_prompt = prompt_or_default(file_prompt);
 
-    let mut conversation_log: Vec<Value> = Vec::new();
+    let mut state = ChatState::new(verbose, choose_model(args.model.clone(), config.model.clone()), provider);
+    apply_request_settings(&mut state, &args, &config);
+    if args.raw {
+        state.format = OutputFormat::Raw;
+    }
+    state.prompt_path = config.system_prompt_path().to_string();
+    if args.log || config.transcript == Some(true) {
+        state.transcript = Some(transcript::Transcript::new());
+    }
+    // The picker needs someone at the keyboard; piped input goes straight to the chat
+    if !args.no_interactive && io::stdin().is_terminal() {
+        picker::run(&mut state);
+    }
+    if let Some(ms) = args.typing_speed.or(config.typing_speed_ms) {
+        state.typing_delay_ms = ms;
+    }
 
     // If there's an initial prompt, add it to the conversation log as a system message
     if !file_prompt.is_empty() {
-        conversation_log.push(json!({"role": "system", "content": file_prompt}));
+        state.conversation_log.push(json!({"role": "system", "content": file_prompt}));
     }
 
-    let stdin = io::stdin();
-    let mut stdout = io::stdout();
+    // --persona (or PERSONA) picks a persona's prompt in place of the prompt file
+    if let Some(name) = args.persona.clone().or_else(|| env::var("PERSONA").ok()) {
+        match persona::load(&name) {
+            Ok(prompt) => {
+                state.set_system_prompt(&prompt);
+                state.persona = Some(name);
+            }
+            Err(err) => eprintln!("Not using persona '{}': {}", name, err),
+        }
+    }
+    if let Some(name) = &state.persona {
+        println!("Active persona: {}", name);
+    }
 
-    loop {
-        print!("You: ");
-        stdout.flush()?;
-        let mut input = String::new();
-        stdin.read_line(&mut input)?;
+    // The last exit's conversation replaces the fresh one. A crash journal, below, is newer still.
+    if args.resume || config.resume == Some(true) {
+        match storage::load_last_session() {
+            Ok(Some(log)) => {
+                println!("Restored {} messages from the last session.", log.len());
+                state.conversation_log = log;
+            }
+            Ok(None) => println!("No last session to resume; starting a new one."),
+            Err(err) => eprintln!("Warning: couldn't read {} ({}); starting a new session.", storage::LAST_SESSION_PATH, err),
+        }
+    }
+
+    // A journal left behind means the last run crashed. It is only replaced once recovered.
+    if wal::crash_detected() {
+        if args.recover {
+            match wal::recover() {
+                Ok((log, session)) => {
+                    println!("Recovered {} messages from the crash journal.", log.len());
+                    state.conversation_log = log;
+                    state.session_name = session;
+                    state.wal = wal::Wal::new(true);
+                }
+                Err(err) => eprintln!("Failed to recover from the crash journal: {}", err),
+            }
+        } else {
+            println!("The last session did not exit cleanly. Run with --recover to restore it; journaling is paused until then.");
+        }
+    } else {
+        if args.recover {
+            println!("No crash journal found; nothing to recover.");
+        }
+        state.wal = wal::Wal::new(wal::enabled());
+    }
+
+    shutdown::listen();
 
-        let user_input = input.trim();
-        if !user_input.is_empty() {
-            conversation_log.push(json!({"role": "user", "content": user_input}));
+    // Startup commands from --init, or INIT_SCRIPT in the environment
+    if let Some(script) = args.init_script.or_else(|| env::var("INIT_SCRIPT").ok()) {
+        commands::run_init_script(&script, &mut state).await;
+    }
+
+    let mut reader = LineReader::new()?;
+    // Set by Ctrl-C during a request: the response is finished and kept, then the chat ends
+    let mut interrupted = false;
+
+    loop {
+        state.wal.sync(&state.conversation_log);
+        // Signals that came in while a command, summary or profile update was running
+        if shutdown::take_terminate() {
+            terminate(&mut state, "Received SIGTERM, shutting down...").await;
+        }
+        if shutdown::take_interrupt() {
+            terminate(&mut state, "Interrupted, shutting down...").await;
         }
 
-        let (tx, rx) = oneshot::channel();
-        let animation_handle = tokio::spawn(async move {
-            animate_thinking(rx).await;
+        // The prompt blocks, so it runs off the async runtime where SIGTERM can interrupt it
+        let live_counts = state.live_counts;
+        let read = tokio::task::spawn_blocking(move || {
+            let line = reader.read_message("You: ", live_counts);
+            (reader, line)
         });
+        let input = tokio::select! {
+            joined = read => {
+                let (returned, line) = joined?;
+                reader = returned;
+                line?
+            }
+            _ = shutdown::terminate_signal() => terminate(&mut state, "Received SIGTERM, shutting down...").await,
+            // A terminal prompt sees Ctrl-C as a key; this catches it when input is piped
+            _ = shutdown::interrupt_signal() => terminate(&mut state, "Interrupted, shutting down...").await,
+        };
+        // None means stdin was closed; exit cleanly instead of looping on empty input
+        let (input, multiline) = match input {
+            Some(Input::Line(line)) => (line, false),
+            Some(Input::Block(block)) => (block, true),
+            None => {
+                println!();
+                break;
+            }
+        };
 
-        let response = query_gpt(&conversation_log, verbose).await?;
+        // A block is sent as typed, apart from blank lines at either end; it is never a command
+        let user_input = if multiline { input.trim_matches(|c| c == '\n' || c == '\r') } else { input.trim() };
 
-        let _ = tx.send(());
-        let _ = animation_handle.await;
+        if let (false, Some(command)) = (multiline, user_input.strip_prefix('/')) {
+            if let CommandResult::Exit = commands::handle_command(command, &mut state).await {
+                break;
+            }
+            continue;
+        }
 
-        print_response_character_by_character(&response).await;
+        // Nothing typed (or only whitespace) just re-prompts; there is nothing to send
+        if user_input.trim().is_empty() {
+            continue;
+        }
 
-        if !response.trim().is_empty() {
-            conversation_log.push(json!({"role": "assistant", "content": response}));
+        let mut content = state.staged_context.join("\n\n");
+        if !content.is_empty() {
+            content.push_str("\n\n");
+        }
+        content.push_str(user_input);
+        if !confirm_if_duplicate(&state.conversation_log, &content) {
+            println!("Not sent.");
+            continue;
+        }
+        state.staged_context.clear();
+        let content = guard_secrets(content);
+        let images = std::mem::take(&mut state.staged_images);
+        if let Some(transcript) = &mut state.transcript {
+            let attached = if images.is_empty() { String::new() } else { format!("\n\n[{} image{} attached]", images.len(), if images.len() == 1 { "" } else { "s" }) };
+            transcript.record("user", &format!("{}{}", content, attached));
+        }
+        state.conversation_log.push(json!({"role": "user", "content": images::user_content(content, images)}));
+
+        let extras = request_extras(&mut state, user_input).await;
+        let mut request_log = build_request(&state, &state.conversation_log, &extras);
+
+        // Short, simple messages may be routed to a cheaper model for this turn only
+        let last_message = state.conversation_log.last().filter(|message| message["role"] == "user").and_then(|message| message["content"].as_str());
+        let turn_model = match last_message.and_then(routing::cheap_model_for) {
+            Some(cheap_model) if cheap_model != state.model => {
+                println!("(Short query: using {} for this turn)", cheap_model);
+                cheap_model
+            }
+            _ => state.model.clone(),
+        };
+
+        let mut result = match fetch_turn(&state, &turn_model, &request_log, &mut interrupted).await {
+            Some(result) => result,
+            None => terminate(&mut state, "Received SIGTERM, shutting down...").await,
+        };
+
+        // Too much context: optionally fold older turns into a summary and retry once
+        if result.as_ref().is_err_and(|err| err.is::<errors::ContextOverflow>()) && errors::compress_on_overflow() && !interrupted {
+            println!("(Context window exceeded; summarizing older turns and retrying...)");
+            match summarize::compress(&mut state).await {
+                Ok(0) => {}
+                Ok(_) => {
+                    // The recalled snippets and addenda go with the compressed log too
+                    request_log = build_request(&state, &state.conversation_log, &extras);
+                    result = match fetch_turn(&state, &turn_model, &request_log, &mut interrupted).await {
+                        Some(result) => result,
+                        None => terminate(&mut state, "Received SIGTERM, shutting down...").await,
+                    };
+                }
+                Err(err) => eprintln!("{}", color::error(&format!("Summary failed: {}", err))),
+            }
+        }
+        // A failed request (still too long, timed out, ...) is reported and its unanswered message
+        // dropped, so the chat carries on and the next turn can succeed
+        let mut response = match result {
+            Ok(response) => response,
+            Err(err) => {
+                if err.is::<errors::ContextOverflow>() {
+                    eprintln!("{}", color::error(&format!("Not sent: {}.", err)));
+                } else {
+                    eprintln!("{}", color::error(&format!("Request failed: {}", err)));
+                }
+                if state.conversation_log.last().is_some_and(|message| message["role"] == "user") {
+                    state.conversation_log.pop();
+                }
+                if interrupted {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        // A blank completion gets one retry in either mode, and is never stored as a turn
+        if is_blank_response(&response) && !interrupted {
+            eprintln!("The model returned an empty response, retrying once...");
+            response = match fetch_turn(&state, &turn_model, &request_log, &mut interrupted).await {
+                Some(Ok(response)) => response,
+                Some(Err(err)) => {
+                    eprintln!("{}", color::error(&format!("Retry failed: {}", err)));
+                    String::new()
+                }
+                None => terminate(&mut state, "Received SIGTERM, shutting down...").await,
+            };
+        }
+
+        // JSON mode asks once more for a reply that doesn't parse, then keeps whatever came back
+        if state.json_mode && state.schema.is_none() && !is_blank_response(&response) && !interrupted {
+            if let Err(err) = serde_json::from_str::<Value>(response.trim()) {
+                eprintln!("Warning: the response is not valid JSON ({}); asking again...", err);
+                response = match fetch_turn(&state, &turn_model, &request_log, &mut interrupted).await {
+                    Some(Ok(retried)) if !is_blank_response(&retried) => retried,
+                    Some(Ok(_)) => response,
+                    Some(Err(err)) => {
+                        eprintln!("{}", color::error(&format!("Retry failed: {}", err)));
+                        response
+                    }
+                    None => terminate(&mut state, "Received SIGTERM, shutting down...").await,
+                };
+                if serde_json::from_str::<Value>(response.trim()).is_err() {
+                    eprintln!("Warning: the response is still not valid JSON; keeping it as is.");
+                }
+            }
+        }
+
+        if is_blank_response(&response) {
+            eprintln!("The model returned an empty response again; nothing was stored.");
+        } else {
+            state.push_assistant(&response);
+        }
+        if interrupted {
+            break;
+        }
+
+        // Keep long sessions manageable by folding old turns into a summary
+        if summarize::auto_summarize_after().is_some_and(|limit| summarize::user_turns(&state.conversation_log) > limit) {
+            println!("(Summarizing older turns to keep the conversation manageable...)");
+            if let Err(err) = summarize::compress(&mut state).await {
+                eprintln!("{}", color::error(&format!("Automatic summary failed: {}", err)));
+            }
+        }
+
+        // Update the profile after a response is generated
+        if profile::updates_enabled() {
+            if let Err(err) = profile::update_profile(&state.conversation_log, state.provider, &state.model, state.verbose).await {
+                eprintln!("{}", color::error(&format!("Profile update failed: {}", err)));
+            }
+        }
+    }
+
+    shutdown::run(&mut state).await;
+    Ok(())
+}
+
+// `command | cli_chatbot --stdin-as-context "question"`: stdin becomes a fenced context block,
+// the argument is the question, and only the answer is written to stdout
+async fn answer_with_stdin_context(question: Option<String>, config: &config::Config, mut state: ChatState) -> Result<(), Box<dyn std::error::Error>> {
+    let question = match question.filter(|question| !question.trim().is_empty()) {
+        Some(question) => question,
+        None => return Err(Box::new(io::Error::other("--stdin-as-context needs the question as an argument, since stdin is used for the context"))),
+    };
+    let mut context = String::new();
+    io::Read::read_to_string(&mut io::stdin(), &mut context)?;
+
+    let prompt = prompt_or_default(read_initial_prompt(config.system_prompt_path(), false).unwrap_or_default());
+    if !prompt.is_empty() {
+        state.conversation_log.push(json!({"role": "system", "content": prompt}));
+    }
+    let content = format!("Context from stdin:\n```text\n{}\n```\n\n{}", context.trim_end(), question);
+    state.conversation_log.push(json!({"role": "user", "content": guard_secrets(content)}));
+
+    let (response, _) = query_gpt_with(&state.conversation_log, state.provider, &state.model, false, &state.request_options()).await?;
+    println!("{}", Renderer::new(state.format).render(&response));
+    Ok(())
+}
+
+// SIGTERM, or Ctrl-C at a piped prompt: take the same exit path as a clean quit, then exit
+// successfully. Any request in flight has already been dropped, which stops its spinner.
+async fn terminate(state: &mut ChatState, reason: &str) -> ! {
+    println!("\r\x1b[K");
+    println!("{}", reason);
+    shutdown::run(state).await;
+    std::process::exit(0);
+}
+
+const DEFAULT_DUPLICATE_SIMILARITY: f32 = 0.95;
+
+// DUPLICATE_CHECK=exact|similar asks before sending a message that repeats the previous user
+// message (similar uses DUPLICATE_SIMILARITY, 0.95 by default). Off unless configured, since
+// repeating yourself is sometimes deliberate. Returns whether to send.
+fn confirm_if_duplicate(conversation_log: &[Value], content: &str) -> bool {
+    let mode = env::var("DUPLICATE_CHECK").unwrap_or_default().to_ascii_lowercase();
+    if mode != "exact" && mode != "similar" {
+        return true;
+    }
+    let previous = match conversation_log.iter().rev().find(|message| message["role"] == "user").and_then(|message| message["content"].as_str()) {
+        Some(previous) => previous.trim(),
+        None => return true,
+    };
+    let duplicate = if mode == "exact" {
+        previous == content.trim()
+    } else {
+        let threshold = env::var("DUPLICATE_SIMILARITY").ok().and_then(|value| value.trim().parse().ok()).unwrap_or(DEFAULT_DUPLICATE_SIMILARITY);
+        diff::similarity(previous, content.trim()) >= threshold
+    };
+    if !duplicate {
+        return true;
+    }
+    print!("This repeats your previous message. Send it anyway? (yes/no) ");
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    let _ = io::stdin().read_line(&mut answer);
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+// Catch API keys pasted into a message before they reach the conversation, the provider or
+// any log. Depending on SECRET_HANDLING they are redacted automatically or after asking.
+fn guard_secrets(content: String) -> String {
+    let handling = secrets::handling();
+    if handling == secrets::KeyHandling::Off {
+        return content;
+    }
+    let found = secrets::count_keys(&content);
+    if found == 0 {
+        return content;
+    }
+    if handling == secrets::KeyHandling::Redact {
+        eprintln!("Warning: redacted {} API key{} from your message.", found, if found == 1 { "" } else { "s" });
+        return secrets::redact_keys(&content);
+    }
+    eprintln!("Warning: your message appears to contain {} API key{}.", found, if found == 1 { "" } else { "s" });
+    print!("Redact before sending? (yes/no) ");
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    let _ = io::stdin().read_line(&mut answer);
+    if answer.trim().eq_ignore_ascii_case("no") || answer.trim().eq_ignore_ascii_case("n") {
+        content
+    } else {
+        secrets::redact_keys(&content)
+    }
+}
+
+// Addendum for JSON mode without a schema; the API requires the messages to mention JSON
+const JSON_MODE_NOTE: &str = "Respond only with a single valid JSON object.";
+
+// Current local date, time and UTC offset, plus the zone name when TZ is set
+fn datetime_note() -> String {
+    let now = chrono::Local::now();
+    let zone = env::var("TZ").ok().filter(|zone| !zone.is_empty()).map(|zone| format!("{}, ", zone)).unwrap_or_default();
+    format!("The current date and time is {} ({}UTC{}).", now.format("%A, %Y-%m-%d %H:%M"), zone, now.format("%:z"))
+}
+
+fn is_blank_response(response: &str) -> bool {
+    response.trim().is_empty()
+}
+
+// Fetch a turn's response. Ctrl-C meanwhile doesn't abort it: `interrupted` is set so the chat
+// ends once the response is in. Returns None if SIGTERM arrives first.
+async fn fetch_turn(state: &ChatState, model: &str, request_log: &[Value], interrupted: &mut bool) -> Option<Result<String, Box<dyn std::error::Error>>> {
+    let fetch = fetch_response(state, model, request_log);
+    tokio::pin!(fetch);
+    loop {
+        tokio::select! {
+            response = &mut fetch => return Some(response),
+            _ = shutdown::terminate_signal() => return None,
+            _ = shutdown::interrupt_signal(), if !*interrupted => *interrupted = true,
+        }
+    }
+}
+
+// Fetch a response from the active provider with the given model, falling back through
+// FALLBACK_CHAIN in order if a request fails
+async fn fetch_response(state: &ChatState, model: &str, request_log: &[Value]) -> Result<String, Box<dyn std::error::Error>> {
+    let mut targets = vec![(state.provider, model.to_string())];
+    targets.extend(state.fallback_chain.iter().cloned());
+    let mut last_error = None;
+    for (attempt, (provider, model)) in targets.iter().enumerate() {
+        match fetch_from(state, *provider, model, request_log).await {
+            Ok(response) => {
+                if attempt > 0 {
+                    println!("(Answered by {} / {})", provider.name(), model);
+                }
+                return Ok(response);
+            }
+            Err(err) => {
+                if let Some((next_provider, next_model)) = targets.get(attempt + 1) {
+                    eprintln!("{}", color::error(&format!("{} / {} failed: {}. Trying {} / {}...", provider.name(), model, err, next_provider.name(), next_model)));
+                }
+                last_error = Some(err);
+            }
         }
     }
+    Err(last_error.unwrap_or_else(|| io::Error::other("no provider configured").into()))
 }
 
-async fn query_gpt(conversation_log: &[Value], verbose: bool) -> Result<String, Box<dyn std::error::Error>> {
-    let api_key = env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY not set");
-    let client = Client::new();
+async fn fetch_from(state: &ChatState, provider: Provider, model: &str, request_log: &[Value]) -> Result<String, Box<dyn std::error::Error>> {
+    let mut spinner = Spinner::start();
+    let options = state.request_options();
+    // Tool calls and alternative replies are handled on complete responses, so a turn that
+    // offers tools or asks for several replies isn't streamed
+    let streamed = state.stream && options.tools.is_none() && state.choices <= 1 && !chat::offline();
+    let result = if streamed {
+        let format = if state.format_chosen { state.format } else { OutputFormat::Raw };
+        query_gpt_stream(request_log, provider, model, state.verbose, format, &options, &mut spinner).await.map(|(content, usage)| (vec![content], usage))
+    } else if chat::offline() {
+        EchoBackend.complete_choices(request_log).await
+    } else {
+        complete_within_deadline(&ApiBackend { provider, model, verbose: state.verbose, options: &options }, request_log).await
+    };
+    // The spinner is stopped on failure too, so a fallback attempt starts on a clean line
+    spinner.stop().await;
+    let (choices, usage) = result?;
+    // Alternatives are all shown as they are offered, so only a single reply is typed out after
+    let picked = choices.len() > 1;
+    let raw = if picked { pick_choice(state, choices) } else { choices.into_iter().next().unwrap_or_default() };
+    // Streamed text is already on screen, so there transforms only affect what is kept
+    let response = state.apply_transforms(&raw);
+    if !streamed && !picked && !is_blank_response(&response) {
+        print_response_character_by_character(&response, state.format, state.typing_delay_ms).await;
+    }
+    // Checked before transforms, which are free to turn the JSON into something else
+    if let Some(schema) = &state.schema {
+        report_schema_mismatch(schema, &raw);
+    }
+    // Providers that don't report usage are counted locally instead
+    let usage = usage.unwrap_or_else(|| {
+        let prompt_tokens = trim::log_tokens(model, request_log) as u64;
+        let completion_tokens = tokens::count_tokens(model, &raw) as u64;
+        Usage { prompt_tokens, completion_tokens, total_tokens: prompt_tokens + completion_tokens }
+    });
+    usage::record(model, &usage, state.show_usage || state.verbose);
+    Ok(response)
+}
+
+// A buffered response has nothing partial to return, so missing the deadline is an error
+async fn complete_within_deadline(backend: &impl ChatBackend, request_log: &[Value]) -> Result<(Vec<String>, Option<Usage>), Box<dyn std::error::Error>> {
+    match stream::response_deadline() {
+        Some(limit) => tokio::time::timeout(limit, backend.complete_choices(request_log))
+            .await
+            .unwrap_or_else(|_| Err(io::Error::other("no response before the response deadline").into())),
+        None => backend.complete_choices(request_log).await,
+    }
+}
+
+// Show each alternative reply, numbered, and return the one picked to keep. Piped input with
+// nothing left to read keeps the first.
+fn pick_choice(state: &ChatState, choices: Vec<String>) -> String {
+    let mut renderer = Renderer::new(state.format);
+    for (index, choice) in choices.iter().enumerate() {
+        println!("Option {}:", index + 1);
+        println!("{}\n", renderer.render(&state.apply_transforms(choice)));
+    }
+    let picked = picker::pick_number(choices.len(), 0);
+    println!("(Kept option {})", picked + 1);
+    choices.into_iter().nth(picked).unwrap_or_default()
+}
+
+// A response that doesn't match the schema is still kept and shown; the mismatch is only reported
+fn report_schema_mismatch(schema: &schema::Schema, response: &str) {
+    let problems = schema.validate(response);
+    if problems.is_empty() {
+        return;
+    }
+    eprintln!("Warning: response does not match schema {}:", schema.path);
+    for problem in problems {
+        eprintln!("  {}", problem);
+    }
+}
+
+async fn query_gpt(conversation_log: &[Value], provider: Provider, model: &str, verbose: bool) -> Result<String, Box<dyn std::error::Error>> {
+    query_gpt_with(conversation_log, provider, model, verbose, &RequestOptions::default()).await.map(|(response, _)| response)
+}
+
+// query_gpt with the chat turn's request options, also returning the reported token usage
+async fn query_gpt_with(conversation_log: &[Value], provider: Provider, model: &str, verbose: bool, options: &RequestOptions) -> Result<(String, Option<Usage>), Box<dyn std::error::Error>> {
+    if chat::offline() {
+        return EchoBackend.complete(conversation_log).await;
+    }
+    ApiBackend { provider, model, verbose, options }.complete(conversation_log).await
+}
+
+// Streamed variant of query_gpt: prints each fragment as it arrives and returns the assembled
+// response along with the usage stats sent in the final chunk
+async fn query_gpt_stream(conversation_log: &[Value], provider: Provider, model: &str, verbose: bool, format: OutputFormat, options: &RequestOptions, spinner: &mut Spinner) -> Result<(String, Option<Usage>), Box<dyn std::error::Error>> {
+    let _in_flight = guard::acquire().await?;
+    // Whatever has arrived by the deadline is returned (and kept) as a partial response
+    let deadline = stream::response_deadline().map(|limit| tokio::time::Instant::now() + limit);
+    let api_key = provider.api_key()?;
+    models::validate_model(provider, model, &api_key, verbose).await?;
+    let client = http::client();
 
-    // Ensure verbose logging is informative and correctly placed
     if verbose {
         println!("Conversation log for API request: {:?}", conversation_log);
     }
 
-    // Correctly structured API request for the chat model
-    let response = client.post("https://api.openai.com/v1/chat/completions")
+    let url = provider.chat_url();
+    let mut body = chat::request_body(model, conversation_log, options, verbose);
+    body["stream"] = json!(true);
+    // Without this the stream carries no token counts at all
+    body["stream_options"] = json!({"include_usage": true});
+
+    audit::record_request(&url, &api_key, &body);
+
+    let request = client.post(&url)
         .header("Content-Type", "application/json")
         .header("Authorization", format!("Bearer {}", api_key))
-        .json(&json!({
-            "model": "gpt-3.5-turbo", // Ensure you're using the correct model identifier
-            "messages": conversation_log, // Pass the conversation log directly
-        }))
-        .send()
-        .await?;
-
-    // Check the response status after the call, before attempting to consume the response body
+        .json(&body);
+    let send = retry::send(request, retry::max_retries(), http::request_timeout());
+    let mut response = match deadline {
+        Some(deadline) => match tokio::time::timeout_at(deadline, send).await {
+            Ok(response) => response.map_err(errors::network_failure)?,
+            Err(_) => return Err(Box::new(io::Error::other("no response before the response deadline"))),
+        },
+        None => send.await.map_err(errors::network_failure)?,
+    };
+
     if verbose {
         println!("Response status: {}", response.status());
     }
 
-    // Assuming the response is successful, parse it
-    if response.status().is_success() {
-        let res: Value = response.json().await?;
-        Ok(res["choices"].get(0).and_then(|choice| choice["message"]["content"].as_str()).unwrap_or_default().to_string())
-    } else {
-        // Handle error responses here
+    if !response.status().is_success() {
+        spinner.stop().await;
+        let status = response.status();
         let error_message = response.text().await?;
-        Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, format!("API call failed: {}", error_message))))
+        return Err(errors::api_failure(provider, status, &error_message));
+    }
+
+    // With a known cap the response is shown as a progress bar, then printed in full
+    let mut progress = options.max_tokens.filter(|_| stream::progress_enabled()).map(ProgressBar::new);
+    // The spinner keeps running until the first token, which then takes over its line
+    let mut started = false;
+
+    let mut parser = SseParser::default();
+    let mut renderer = StreamRenderer::new(format);
+    let mut output = OutputBuffer::from_env();
+    let mut content = String::new();
+    let mut content_chars = 0;
+    let max_chars = stream::max_stream_chars();
+    let mut truncated = false;
+    let mut usage = None;
+    let mut done = false;
+    let mut partial = false;
+
+    while !done {
+        // Pending output is flushed on schedule even while the network is quiet, and the
+        // response deadline cuts the stream off wherever it has got to
+        let flush_at = output.time_until_flush().map(|wait| tokio::time::Instant::now() + wait);
+        let wake_at = [flush_at, deadline].iter().flatten().min().copied();
+        let chunk = match wake_at {
+            Some(wake_at) => match tokio::time::timeout_at(wake_at, response.chunk()).await {
+                Ok(chunk) => chunk?,
+                Err(_) if deadline.is_some_and(|deadline| tokio::time::Instant::now() >= deadline) => {
+                    partial = true;
+                    break;
+                }
+                Err(_) => {
+                    output.flush()?;
+                    continue;
+                }
+            },
+            None => response.chunk().await?,
+        };
+        let events = match chunk {
+            Some(chunk) => parser.push(&chunk),
+            None => {
+                done = true;
+                parser.finish().into_iter().collect()
+            }
+        };
+
+        for data in events {
+            if data == "[DONE]" {
+                done = true;
+                break;
+            }
+            let event: Value = match serde_json::from_str(&data) {
+                Ok(event) => event,
+                Err(err) => {
+                    if verbose {
+                        eprintln!("Skipping malformed stream event: {}", err);
+                    }
+                    continue;
+                }
+            };
+            // Errors after the response has started arrive as an event rather than a status code
+            if !event["error"].is_null() {
+                begin_stream_output(spinner, &mut progress, &mut started).await?;
+                output.flush()?;
+                println!();
+                let message = event["error"]["message"].as_str().map(str::to_string).unwrap_or_else(|| event["error"].to_string());
+                return Err(Box::new(io::Error::other(format!("stream failed: {}", message))));
+            }
+            if let Some(fragment) = event["choices"].get(0).and_then(|choice| choice["delta"]["content"].as_str()).filter(|fragment| !fragment.is_empty()) {
+                begin_stream_output(spinner, &mut progress, &mut started).await?;
+                let remaining = max_chars - content_chars;
+                let fragment_chars = fragment.chars().count();
+                let fragment: String = fragment.chars().take(remaining).collect();
+                match progress.as_mut() {
+                    // Each content delta carries roughly one token
+                    Some(bar) => bar.advance(1)?,
+                    None => output.push(&renderer.push(&fragment))?,
+                }
+                content.push_str(&fragment);
+                content_chars += fragment_chars.min(remaining);
+
+                if fragment_chars > remaining {
+                    truncated = true;
+                    done = true;
+                    break;
+                }
+            }
+            // The usage chunk arrives last, with an empty choices array
+            if let Some(parsed) = Usage::from_json(&event["usage"]) {
+                usage = Some(parsed);
+            }
+        }
+    }
+
+    // Dropping the response closes the connection, so a runaway stream stops here
+    drop(response);
+    // An empty response still gets its prefix
+    begin_stream_output(spinner, &mut progress, &mut started).await?;
+
+    match progress {
+        Some(bar) => {
+            bar.clear()?;
+            println!("{} {}", color::bot(), Renderer::new(format).render(&content));
+        }
+        None => {
+            output.push(&renderer.finish())?;
+            output.flush()?;
+            println!();
+        }
+    }
+    if truncated {
+        eprintln!("[Response truncated at {} characters]", max_chars);
+    }
+    if partial {
+        eprintln!("[Partial response: stopped at the response deadline]");
     }
+    Ok((content, usage))
 }
 
+// Swap the spinner for the response prefix (or the progress bar) the first time it is called
+async fn begin_stream_output(spinner: &mut Spinner, progress: &mut Option<ProgressBar>, started: &mut bool) -> Result<(), io::Error> {
+    if *started {
+        return Ok(());
+    }
+    *started = true;
+    spinner.stop().await;
+    match progress.as_mut() {
+        Some(bar) => bar.draw(),
+        None => {
+            print!("{} ", color::bot());
+            io::stdout().flush()
+        }
+    }
+}
 
-async fn animate_thinking(mut stop_signal: oneshot::Receiver<()>) {
-    let mut dots = 0;
-    loop {
-        if stop_signal.try_recv().is_ok() {
-            println!("\rThinking{} ", " ".repeat(6)); // Clear the line and add space for transition
-            break;
+// The "Thinking..." animation running in the background while a request is in flight
+struct Spinner {
+    stop_signal: Option<oneshot::Sender<()>>,
+    handle: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl Spinner {
+    fn start() -> Spinner {
+        let (tx, rx) = oneshot::channel();
+        let handle = tokio::spawn(async move {
+            animate_thinking(rx).await;
+        });
+        Spinner { stop_signal: Some(tx), handle: Some(handle) }
+    }
+
+    // Stop the animation and wait for it to clear its line; safe to call more than once
+    async fn stop(&mut self) {
+        if let Some(tx) = self.stop_signal.take() {
+            let _ = tx.send(());
         }
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.await;
+        }
+    }
+}
 
-        if dots == 6 {
-            print!("\rThinking{}", " ".repeat(6)); // Clear the dots visually
-            dots = 0;
-        } else {
-            print!("\rThinking{}", ".".repeat(dots));
-            dots += 1;
+// A spinner dropped without being stopped (e.g. its request was cancelled) must not keep
+// animating in the background, or leave the cursor hidden
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+            if io::stdout().is_terminal() {
+                print!("{}{}", CLEAR_LINE, SHOW_CURSOR);
+                let _ = io::stdout().flush();
+            }
+        }
+    }
+}
+
+const HIDE_CURSOR: &str = "\x1b[?25l";
+const SHOW_CURSOR: &str = "\x1b[?25h";
+const CLEAR_LINE: &str = "\r\x1b[2K";
+
+// Frames for SPINNER_STYLE: dots (the default), braille or line. Each frame follows the text.
+fn spinner_frames() -> Vec<String> {
+    match env::var("SPINNER_STYLE").unwrap_or_default().to_ascii_lowercase().as_str() {
+        "braille" => "⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏".chars().map(|c| format!(" {}", c)).collect(),
+        "line" => "|/-\\".chars().map(|c| format!(" {}", c)).collect(),
+        _ => (0..=6).map(|dots| ".".repeat(dots)).collect(),
+    }
+}
+
+// Columns in the terminal, from the tty itself, then COLUMNS, then a conventional 80
+fn terminal_width() -> usize {
+    #[cfg(unix)]
+    {
+        // SAFETY: TIOCGWINSZ only fills in the winsize struct it is given
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0 && size.ws_col > 0 {
+            return usize::from(size.ws_col);
+        }
+    }
+    env::var("COLUMNS").ok().and_then(|columns| columns.parse().ok()).filter(|&columns| columns > 0).unwrap_or(80)
+}
+
+// Redraw "Thinking" (or SPINNER_TEXT) with the next frame until stopped. Each frame clears the
+// whole line and is cut to the terminal width, so it never wraps; the cursor is hidden
+// meanwhile. Nothing is drawn when stdout isn't a terminal.
+async fn animate_thinking(mut stop_signal: oneshot::Receiver<()>) {
+    if !io::stdout().is_terminal() {
+        let _ = stop_signal.await;
+        return;
+    }
+    let text = env::var("SPINNER_TEXT").unwrap_or_else(|_| "Thinking".to_string());
+    let frames = spinner_frames();
+    print!("{}", HIDE_CURSOR);
+    for frame in frames.iter().cycle() {
+        // Width is read every frame, so resizing mid-request is handled too
+        let line: String = format!("{}{}", text, frame).chars().take(terminal_width().saturating_sub(1)).collect();
+        print!("{}{}", CLEAR_LINE, line);
+        let _ = io::stdout().flush();
+        // Woken by the stop signal mid-frame, so the first token isn't kept waiting
+        tokio::select! {
+            _ = &mut stop_signal => break,
+            _ = sleep(Duration::from_millis(100)) => {}
         }
-        io::stdout().flush().unwrap();
-        sleep(Duration::from_millis(100)).await;
     }
+    print!("{}{}", CLEAR_LINE, SHOW_CURSOR);
+    let _ = io::stdout().flush();
 }
  
-async fn print_response_character_by_character(response: &String) {
-    print!("Bot: "); // Print the "Bot: " prefix before the response
-    for c in response.chars() {
+async fn print_response_character_by_character(response: &str, format: OutputFormat, delay_ms: u64) {
+    let rendered = Renderer::new(format).render(response);
+    print!("{} ", color::bot()); // Print the "Bot: " prefix before the response
+    if delay_ms == 0 {
+        println!("{}", rendered);
+        return;
+    }
+    // Space pauses and resumes typing, any other key skips to the end; the whole response is
+    // already in the log either way
+    let keys = keys::KeyWatcher::start();
+    let mut chars = rendered.chars();
+    while let Some(c) = chars.next() {
         print!("{}", c);
+        // Print ANSI escape sequences in one go rather than typing them out
+        if c == '\x1b' {
+            for c in chars.by_ref() {
+                print!("{}", c);
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+            continue;
+        }
         io::stdout().flush().unwrap();
-        sleep(Duration::from_millis(10)).await;
+        sleep(Duration::from_millis(delay_ms)).await;
+        if let Some(keys) = &keys {
+            if skip_requested(keys).await {
+                print!("{}", chars.as_str());
+                break;
+            }
+        }
     }
     println!(); // Ensure the output ends on a new line
 }
+
+// Handle keys pressed since the last character. Space pauses until it is pressed again; any
+// other key, paused or not, asks to skip the rest of the typing.
+async fn skip_requested(keys: &keys::KeyWatcher) -> bool {
+    let mut paused = false;
+    loop {
+        while let Some(key) = keys.poll() {
+            if key != b' ' {
+                return true;
+            }
+            paused = !paused;
+        }
+        if !paused {
+            return false;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // Stands in for the API: records what a chat turn sent and answers with a fixed reply
+    struct FakeBackend {
+        sent: Mutex<Vec<Value>>,
+    }
+
+    impl ChatBackend for FakeBackend {
+        async fn complete(&self, messages: &[Value]) -> Result<(String, Option<Usage>), Box<dyn std::error::Error>> {
+            *self.sent.lock().unwrap() = messages.to_vec();
+            Ok(("Canned reply".to_string(), None))
+        }
+    }
+
+    fn state_with_turns(turns: usize) -> ChatState {
+        let mut state = ChatState::new(false, "gpt-4o-mini".to_string(), Provider::OpenAI);
+        state.conversation_log.push(json!({"role": "system", "content": "You are terse."}));
+        for turn in 0..turns {
+            state.conversation_log.push(json!({"role": "user", "content": format!("Question number {} with some padding text", turn)}));
+            state.conversation_log.push(json!({"role": "assistant", "content": format!("Answer number {} with some padding text", turn)}));
+        }
+        state.conversation_log.push(json!({"role": "user", "content": "Latest question"}));
+        state
+    }
+
+    #[tokio::test]
+    async fn chat_turn_sends_extras_before_the_last_message() {
+        let state = state_with_turns(2);
+        let extras = vec![json!({"role": "system", "content": "Recalled context"})];
+        let request = build_request(&state, &state.conversation_log, &extras);
+        let backend = FakeBackend { sent: Mutex::new(Vec::new()) };
+        let (choices, _) = complete_within_deadline(&backend, &request).await.unwrap();
+
+        assert_eq!(choices, vec!["Canned reply".to_string()]);
+        let sent = backend.sent.lock().unwrap();
+        assert_eq!(sent.len(), state.conversation_log.len() + 1);
+        assert_eq!(sent[sent.len() - 2]["content"], "Recalled context");
+        assert_eq!(sent[sent.len() - 1]["content"], "Latest question");
+    }
+
+    #[test]
+    fn budget_trim_keeps_extras_and_the_last_message() {
+        let mut state = state_with_turns(6);
+        let extras = vec![json!({"role": "system", "content": "Recalled context"}), json!({"role": "system", "content": "Addenda"})];
+        // Far too small for the history, so every older turn is dropped
+        state.context_budget = Some(10);
+        let request = build_request(&state, &state.conversation_log, &extras);
+        let roles: Vec<&str> = request.iter().map(|message| message["role"].as_str().unwrap()).collect();
+        assert_eq!(roles, vec!["system", "system", "system", "user"]);
+        assert_eq!(request[3]["content"], "Latest question");
+    }
+}
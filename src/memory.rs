@@ -0,0 +1,68 @@
+use crate::files;
+use crate::images;
+use crate::provider::Provider;
+use crate::query_gpt;
+use chrono::Local;
+use serde_json::{json, Value};
+use std::env;
+use std::fs;
+use std::path::Path;
+
+const DEFAULT_MEMORY_FILE: &str = "memories/learnings.md";
+const DEFAULT_PROMPT_PATH: &str = "system_prompts/memory_extract.md";
+const FALLBACK_PROMPT: &str = "Extract the key facts worth remembering about the user from this conversation, one per line starting with \"- \". If there is nothing worth remembering, output exactly: NONE";
+
+// The exit-time memory hook is opt-in via MEMORY_ON_EXIT
+pub fn enabled() -> bool {
+    env::var("MEMORY_ON_EXIT").map(|value| value == "1" || value.eq_ignore_ascii_case("true")).unwrap_or(false)
+}
+
+fn memory_file() -> String {
+    env::var("MEMORY_FILE").unwrap_or_else(|_| DEFAULT_MEMORY_FILE.to_string())
+}
+
+// Extraction prompt from MEMORY_PROMPT_PATH (or the bundled prompt file), with a built-in fallback
+fn extraction_prompt() -> String {
+    let path = env::var("MEMORY_PROMPT_PATH").unwrap_or_else(|_| DEFAULT_PROMPT_PATH.to_string());
+    fs::read_to_string(path)
+        .ok()
+        .filter(|prompt| !prompt.trim().is_empty())
+        .unwrap_or_else(|| FALLBACK_PROMPT.to_string())
+}
+
+// Plain-text transcript of the session, leaving out the system prompt
+fn transcript(conversation_log: &[Value]) -> String {
+    conversation_log
+        .iter()
+        .filter(|message| message["role"] != "system")
+        .map(|message| format!("{}: {}", message["role"].as_str().unwrap_or_default(), images::text_of(&message["content"])))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+// Ask the model for the session's key facts and append them to the memory file.
+// Returns the path written to, or None if there was nothing to save.
+pub async fn save_learnings(conversation_log: &[Value], provider: Provider, model: &str, verbose: bool) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    if !conversation_log.iter().any(|message| message["role"] == "user") {
+        return Ok(None);
+    }
+
+    let request = vec![
+        json!({"role": "system", "content": extraction_prompt()}),
+        json!({"role": "user", "content": transcript(conversation_log)}),
+    ];
+    let facts = query_gpt(&request, provider, model, verbose).await?;
+    let facts = facts.trim();
+    if facts.is_empty() || facts == "NONE" {
+        return Ok(None);
+    }
+
+    let path = memory_file();
+    let mut contents = fs::read_to_string(&path).unwrap_or_default();
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        contents.push('\n');
+    }
+    contents.push_str(&format!("\n## {}\n{}\n", Local::now().format("%Y-%m-%d %H:%M"), facts));
+    files::write_atomic(Path::new(&path), &contents)?;
+    Ok(Some(path))
+}
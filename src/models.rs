@@ -0,0 +1,110 @@
+use crate::http;
+use crate::provider::Provider;
+use reqwest::Client;
+use serde_json::Value;
+use std::env;
+use std::io;
+use std::sync::Mutex;
+
+// Model ids fetched per provider, kept for the rest of the process
+static MODEL_CACHE: Mutex<Vec<(Provider, Vec<String>)>> = Mutex::new(Vec::new());
+// Models already warned about, so a typo is reported once rather than every turn
+static WARNED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+#[derive(PartialEq)]
+enum ValidationMode {
+    Off,
+    Warn,
+    Error,
+}
+
+// MODEL_VALIDATION=off|warn|error, warning by default
+fn validation_mode() -> ValidationMode {
+    match env::var("MODEL_VALIDATION").unwrap_or_default().to_ascii_lowercase().as_str() {
+        "off" | "0" | "false" => ValidationMode::Off,
+        "error" => ValidationMode::Error,
+        _ => ValidationMode::Warn,
+    }
+}
+
+async fn fetch_models(client: &Client, url: &str, api_key: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let request = client.get(url)
+        .header("Authorization", format!("Bearer {}", api_key));
+    let response = http::limited(request).send().await?;
+    if !response.status().is_success() {
+        return Err(Box::new(io::Error::other(format!("model list request failed: {}", response.status()))));
+    }
+    let res: Value = response.json().await?;
+    Ok(res["data"]
+        .as_array()
+        .map(|models| models.iter().filter_map(|model| model["id"].as_str().map(str::to_string)).collect())
+        .unwrap_or_default())
+}
+
+// Known model ids, fetched once per provider. Returns None for providers without a model
+// list or when it can't be retrieved, in which case validation is skipped.
+async fn known_models(provider: Provider, api_key: &str, verbose: bool) -> Option<Vec<String>> {
+    let url = provider.models_url()?;
+    if let Some((_, models)) = MODEL_CACHE.lock().unwrap().iter().find(|(cached, _)| *cached == provider) {
+        return Some(models.clone());
+    }
+    match fetch_models(http::client(), &url, api_key).await {
+        Ok(models) if !models.is_empty() => {
+            MODEL_CACHE.lock().unwrap().push((provider, models.clone()));
+            Some(models)
+        }
+        Ok(_) => None,
+        Err(err) => {
+            if verbose {
+                eprintln!("Skipping model validation: {}", err);
+            }
+            None
+        }
+    }
+}
+
+// Levenshtein distance, used to suggest the closest known model for a typo
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+pub fn closest_model<'a>(model: &str, known: &'a [String]) -> Option<&'a str> {
+    known.iter().min_by_key(|candidate| edit_distance(model, candidate)).map(String::as_str)
+}
+
+// Check the model against the provider's list before sending. Unknown models produce a
+// warning (once per model) or, with MODEL_VALIDATION=error, an error.
+pub async fn validate_model(provider: Provider, model: &str, api_key: &str, verbose: bool) -> Result<(), io::Error> {
+    let mode = validation_mode();
+    if mode == ValidationMode::Off || WARNED.lock().unwrap().iter().any(|warned| warned == model) {
+        return Ok(());
+    }
+    let known = match known_models(provider, api_key, verbose).await {
+        Some(known) => known,
+        None => return Ok(()),
+    };
+    if known.iter().any(|candidate| candidate == model) {
+        return Ok(());
+    }
+
+    let mut message = format!("unknown model '{}'", model);
+    if let Some(suggestion) = closest_model(model, &known) {
+        message.push_str(&format!(" (did you mean '{}'?)", suggestion));
+    }
+    if mode == ValidationMode::Error {
+        return Err(io::Error::other(message));
+    }
+    eprintln!("Warning: {}", message);
+    WARNED.lock().unwrap().push(model.to_string());
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use std::env;
+use std::fs;
+
+// Every <name>.md here is a persona, apart from the prompts used internally
+const PERSONA_DIR: &str = "system_prompts";
+const INTERNAL_PROMPTS: &[&str] = &["memory_extract", "user_update"];
+
+// Personas from PERSONAS, a comma-separated map of name to system prompt file, e.g.
+// "coder=system_prompts/coder.md,tutor=system_prompts/tutor.md", followed by the prompt files
+// in system_prompts/. A PERSONAS entry wins over a file of the same name.
+pub fn configured() -> Vec<(String, String)> {
+    let mut personas = from_env();
+    for (name, path) in from_dir() {
+        if !personas.iter().any(|(existing, _)| *existing == name) {
+            personas.push((name, path));
+        }
+    }
+    personas
+}
+
+fn from_env() -> Vec<(String, String)> {
+    let spec = env::var("PERSONAS").unwrap_or_default();
+    spec.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| match entry.split_once('=') {
+            Some((name, path)) if !name.trim().is_empty() && !path.trim().is_empty() => Some((name.trim().to_string(), path.trim().to_string())),
+            _ => {
+                eprintln!("Ignoring persona entry '{}': expected name=path", entry);
+                None
+            }
+        })
+        .collect()
+}
+
+// A missing or unreadable directory just has no personas
+fn from_dir() -> Vec<(String, String)> {
+    let entries = match fs::read_dir(PERSONA_DIR) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut personas: Vec<(String, String)> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("md"))
+        .filter_map(|path| {
+            let name = path.file_stem()?.to_str()?.to_string();
+            Some((name, path.to_str()?.to_string()))
+        })
+        .filter(|(name, _)| !INTERNAL_PROMPTS.contains(&name.as_str()))
+        .collect();
+    personas.sort();
+    personas
+}
+
+// The system prompt for a persona, or a message explaining why it can't be used
+pub fn load(name: &str) -> Result<String, String> {
+    let personas = configured();
+    let path = match personas.iter().find(|(persona, _)| persona == name) {
+        Some((_, path)) => path,
+        None if personas.is_empty() => return Err(format!("no personas found (add {}/<name>.md, or set PERSONAS=name=path,...)", PERSONA_DIR)),
+        None => return Err(format!("unknown persona '{}'; available: {}", name, personas.iter().map(|(persona, _)| persona.as_str()).collect::<Vec<_>>().join(", "))),
+    };
+    match fs::read_to_string(path) {
+        Ok(prompt) if prompt.trim().is_empty() => Err(format!("the prompt file {} is empty", path)),
+        Ok(prompt) => Ok(prompt),
+        Err(err) => Err(format!("failed to read {}: {}", path, err)),
+    }
+}
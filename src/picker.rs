@@ -0,0 +1,96 @@
+use crate::ChatState;
+use std::env;
+use std::io::{self, Write};
+
+// Offered alongside the configured model unless MODEL_CHOICES (comma-separated) replaces them
+const DEFAULT_MODEL_CHOICES: &[&str] = &["gpt-4o-mini", "gpt-4o", "gpt-4.1-mini", "gpt-4.1", "gpt-3.5-turbo"];
+
+// Read an answer, None at end of input so a closed stdin falls back to the default instead of
+// re-prompting forever
+fn read_answer(prompt: &str) -> Option<String> {
+    print!("{}", prompt);
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    match io::stdin().read_line(&mut answer) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(answer.trim().to_string()),
+    }
+}
+
+// Print numbered options and return the index picked. Enter keeps `default`.
+fn choose(title: &str, options: &[String], default: usize) -> usize {
+    println!("{}:", title);
+    for (index, option) in options.iter().enumerate() {
+        println!("  {}) {}", index + 1, option);
+    }
+    pick_number(options.len(), default)
+}
+
+// Ask for one of `count` numbered options already on screen and return its index. Enter (or
+// closed input) keeps `default`.
+pub fn pick_number(count: usize, default: usize) -> usize {
+    loop {
+        let answer = match read_answer(&format!("Choose 1-{} [{}]: ", count, default + 1)) {
+            Some(answer) if !answer.is_empty() => answer,
+            _ => return default,
+        };
+        match answer.parse::<usize>() {
+            Ok(number) if (1..=count).contains(&number) => return number - 1,
+            _ => println!("Please enter a number from 1 to {}.", count),
+        }
+    }
+}
+
+fn ask_temperature(default: f64) -> f64 {
+    loop {
+        let answer = match read_answer(&format!("Temperature (0-2) [{}]: ", default)) {
+            Some(answer) if !answer.is_empty() => answer,
+            _ => return default,
+        };
+        match answer.parse::<f64>() {
+            Ok(temperature) if (0.0..=2.0).contains(&temperature) => return temperature,
+            _ => println!("Please enter a number from 0 to 2."),
+        }
+    }
+}
+
+fn ask_yes_no(question: &str, default: bool) -> bool {
+    loop {
+        let answer = match read_answer(&format!("{} (yes/no) [{}]: ", question, if default { "yes" } else { "no" })) {
+            Some(answer) if !answer.is_empty() => answer.to_ascii_lowercase(),
+            _ => return default,
+        };
+        match answer.as_str() {
+            "y" | "yes" => return true,
+            "n" | "no" => return false,
+            _ => println!("Please answer yes or no."),
+        }
+    }
+}
+
+fn model_choices(current: &str) -> Vec<String> {
+    let configured = env::var("MODEL_CHOICES").ok().filter(|choices| !choices.trim().is_empty());
+    let mut choices = vec![current.to_string()];
+    let others: Vec<String> = match configured {
+        Some(list) => list.split(',').map(|model| model.trim().to_string()).filter(|model| !model.is_empty()).collect(),
+        None => DEFAULT_MODEL_CHOICES.iter().map(|model| model.to_string()).collect(),
+    };
+    for model in others {
+        if !choices.contains(&model) {
+            choices.push(model);
+        }
+    }
+    choices
+}
+
+// Startup menu for the model, temperature and streaming, each defaulting to what the config
+// and environment already chose
+pub fn run(state: &mut ChatState) {
+    println!("Startup settings (press Enter to keep the value in brackets)");
+    let models = model_choices(&state.model);
+    let picked = choose("Model", &models, 0);
+    state.model = models[picked].clone();
+    state.temperature = ask_temperature(state.temperature);
+    state.stream = ask_yes_no("Stream responses?", state.stream);
+    println!("Using {} at temperature {}{}.", state.model, state.temperature, if state.stream { ", streaming" } else { "" });
+}
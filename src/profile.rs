@@ -0,0 +1,219 @@
+use crate::files;
+use crate::images;
+use crate::provider::Provider;
+use crate::chat::RequestOptions;
+use crate::query_gpt_with;
+use chrono::Local;
+use difflib::sequencematcher::SequenceMatcher;
+use serde_json::{json, Value};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// The profile the experimental updater maintains about the user
+pub const USER_PROFILE_PATH: &str = "memories/userprofile.txt";
+// Timestamped copies of the profile taken before each update replaces it
+const BACKUP_DIR: &str = "memories/backups";
+const DEFAULT_BACKUP_COUNT: usize = 5;
+const UPDATE_PROMPT_PATH: &str = "system_prompts/user_update.md";
+// Updates less similar than this to the current profile are rolled back
+const DEFAULT_MIN_SIMILARITY: f32 = 0.6;
+
+// What the last profile update computed, kept so /profile-opcodes can explain its decision
+#[derive(Clone)]
+pub struct UpdateReport {
+    // (tag, old start, old end, new start, new end) over the profile's characters
+    pub opcodes: Vec<(String, usize, usize, usize, usize)>,
+    pub differences: usize,
+    // Share of the two profiles that matches (SequenceMatcher ratio), and the floor under which
+    // the update is rolled back
+    pub similarity: f32,
+    pub min_similarity: f32,
+    pub rolled_back: bool,
+}
+
+static LAST_UPDATE: Mutex<Option<UpdateReport>> = Mutex::new(None);
+// The user messages earlier updates have already covered. Kept by content rather than as a
+// count, so messages still count as new after /clear, /undo, /load or a summary.
+static COVERED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+// The experimental profile updater runs after each response when PROFILE_UPDATES is set
+pub fn updates_enabled() -> bool {
+    env::var("PROFILE_UPDATES").map(|value| value == "1" || value.eq_ignore_ascii_case("true")).unwrap_or(false)
+}
+
+// PROFILE_MIN_SIMILARITY, between 0 and 1
+fn min_similarity() -> f32 {
+    env::var("PROFILE_MIN_SIMILARITY")
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .filter(|ratio: &f32| (0.0..=1.0).contains(ratio))
+        .unwrap_or(DEFAULT_MIN_SIMILARITY)
+}
+
+// PROFILE_BACKUPS, how many backups to keep; 0 keeps none
+fn backup_count() -> usize {
+    env::var("PROFILE_BACKUPS").ok().and_then(|value| value.trim().parse().ok()).unwrap_or(DEFAULT_BACKUP_COUNT)
+}
+
+// Save the profile as memories/backups/userprofile-<timestamp>.txt, then prune the oldest
+// backups beyond the limit. The timestamps sort in the order they were taken.
+fn backup_profile(data: &str) -> Result<(), io::Error> {
+    let keep = backup_count();
+    if keep == 0 || data.trim().is_empty() {
+        return Ok(());
+    }
+    fs::create_dir_all(BACKUP_DIR)?;
+    let name = format!("userprofile-{}.txt", Local::now().format("%Y%m%d-%H%M%S%.3f"));
+    files::write(&Path::new(BACKUP_DIR).join(name), data)?;
+
+    let mut backups: Vec<PathBuf> = fs::read_dir(BACKUP_DIR)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with("userprofile-") && name.ends_with(".txt")))
+        .collect();
+    backups.sort();
+    let excess = backups.len().saturating_sub(keep);
+    for old in &backups[..excess] {
+        files::confirm_write(old)?;
+        fs::remove_file(old)?;
+    }
+    Ok(())
+}
+
+pub fn last_update() -> Option<UpdateReport> {
+    LAST_UPDATE.lock().unwrap().clone()
+}
+
+pub fn read_profile() -> Result<String, io::Error> {
+    fs::read_to_string(USER_PROFILE_PATH)
+}
+
+// Pretty-printed profile when it holds JSON (optionally wrapped in the
+// <USER PROFILE START>/<USER PROFILE END> markers from the update prompt)
+pub fn pretty_profile(data: &str) -> Option<String> {
+    let inner = data
+        .trim()
+        .trim_start_matches("<USER PROFILE START>")
+        .trim_end_matches("<USER PROFILE END>")
+        .trim()
+        .trim_start_matches("```json")
+        .trim_start_matches("```")
+        .trim_end_matches("```")
+        .trim();
+    let value: Value = serde_json::from_str(inner).ok()?;
+    serde_json::to_string_pretty(&value).ok()
+}
+
+// Compare an updated profile with the current one and decide whether to roll it back.
+// Compared by character rather than by line: most updates change a value inside a line, and
+// a ratio (unlike a count of differing runs) means the same thing for small and large profiles.
+fn compare_profiles(original: &str, updated: &str, min_similarity: f32) -> UpdateReport {
+    let original_chars: Vec<char> = original.chars().collect();
+    let updated_chars: Vec<char> = updated.chars().collect();
+    let mut matcher = SequenceMatcher::new(&original_chars, &updated_chars);
+    let opcodes: Vec<(String, usize, usize, usize, usize)> = matcher
+        .get_opcodes()
+        .into_iter()
+        .map(|opcode| (opcode.tag, opcode.first_start, opcode.first_end, opcode.second_start, opcode.second_end))
+        .collect();
+    let differences = opcodes.iter().filter(|(tag, ..)| tag != "equal").count();
+    let similarity = matcher.ratio();
+    // An empty profile has nothing to protect, so the first update is always kept
+    let rolled_back = !original.trim().is_empty() && similarity < min_similarity;
+    UpdateReport { opcodes, differences, similarity, min_similarity, rolled_back }
+}
+
+// Ask the model for an updated profile from the user messages sent since the last update, and
+// keep it unless it differs so much from the current one that it is rolled back. The current
+// profile is backed up before every accepted update.
+// Returns None without asking when there is no new user message. Ported from the experimental
+// build.
+pub async fn update_profile(conversation_log: &[Value], provider: Provider, model: &str, verbose: bool) -> Result<Option<UpdateReport>, Box<dyn std::error::Error>> {
+    let user_messages: Vec<_> = conversation_log
+        .iter()
+        .filter(|message| message["role"] == "user")
+        .map(|message| images::text_of(&message["content"]))
+        .collect();
+    let new_messages: Vec<_> = {
+        let covered = COVERED.lock().unwrap();
+        user_messages.iter().filter(|message| !covered.iter().any(|seen| seen.as_str() == message.as_ref())).collect()
+    };
+    if new_messages.is_empty() {
+        return Ok(None);
+    }
+
+    let original_data = read_profile()?;
+    let update_prompt = fs::read_to_string(UPDATE_PROMPT_PATH)?;
+    let content = format!(
+        "Current user profile:\n{}\n\nNew user messages:\n{}",
+        original_data.trim(),
+        new_messages.iter().map(|message| format!("- {}", message)).collect::<Vec<_>>().join("\n")
+    );
+    let update_data = vec![
+        json!({"role": "system", "content": update_prompt}),
+        json!({"role": "user", "content": content}),
+    ];
+    // Profile edits should be as repeatable as possible
+    let options = RequestOptions { temperature: Some(0.0), ..RequestOptions::default() };
+    let (user_profile_updated, _) = query_gpt_with(&update_data, provider, model, verbose, &options).await?;
+    COVERED.lock().unwrap().extend(new_messages.iter().map(|message| message.to_string()));
+
+    let report = compare_profiles(&original_data, &user_profile_updated, min_similarity());
+    // A rolled-back update never touches the file, so the current profile simply stays
+    if !report.rolled_back {
+        backup_profile(&original_data)?;
+        files::write(Path::new(USER_PROFILE_PATH), &user_profile_updated)?;
+    }
+
+    *LAST_UPDATE.lock().unwrap() = Some(report.clone());
+    Ok(Some(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SMALL: &str = "Name: Sam\nLikes: tea";
+
+    fn large() -> String {
+        (1..=40).map(|line| format!("Fact {}: the user mentioned detail number {}.", line, line)).collect::<Vec<_>>().join("\n")
+    }
+
+    #[test]
+    fn small_profile_with_a_small_edit_is_kept() {
+        let report = compare_profiles(SMALL, "Name: Sam\nLikes: coffee", DEFAULT_MIN_SIMILARITY);
+        assert!(report.similarity >= DEFAULT_MIN_SIMILARITY, "similarity {}", report.similarity);
+        assert!(!report.rolled_back);
+    }
+
+    #[test]
+    fn small_profile_rewritten_is_rolled_back() {
+        let report = compare_profiles(SMALL, "Completely different text about nothing", DEFAULT_MIN_SIMILARITY);
+        assert!(report.similarity < DEFAULT_MIN_SIMILARITY, "similarity {}", report.similarity);
+        assert!(report.rolled_back);
+    }
+
+    #[test]
+    fn large_profile_with_one_changed_line_is_kept() {
+        let original = large();
+        let updated = original.replace("detail number 7.", "detail number 7, and now likes hiking.");
+        let report = compare_profiles(&original, &updated, DEFAULT_MIN_SIMILARITY);
+        assert!(report.differences > 0);
+        assert!(!report.rolled_back, "similarity {}", report.similarity);
+    }
+
+    #[test]
+    fn large_profile_mostly_dropped_is_rolled_back() {
+        let original = large();
+        let updated: String = original.lines().take(5).collect::<Vec<_>>().join("\n");
+        let report = compare_profiles(&original, &updated, DEFAULT_MIN_SIMILARITY);
+        assert!(report.rolled_back, "similarity {}", report.similarity);
+    }
+
+    #[test]
+    fn empty_profile_is_never_rolled_back() {
+        assert!(!compare_profiles("", "Name: Sam", DEFAULT_MIN_SIMILARITY).rolled_back);
+    }
+}
@@ -0,0 +1,112 @@
+use std::env;
+use std::io;
+
+// Chat-completions compatible API providers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Provider {
+    OpenAI,
+    OpenRouter,
+    Anthropic,
+    Ollama,
+}
+
+impl Provider {
+    pub fn from_name(name: &str) -> Option<Provider> {
+        match name.to_ascii_lowercase().as_str() {
+            "openai" => Some(Provider::OpenAI),
+            "openrouter" => Some(Provider::OpenRouter),
+            "anthropic" => Some(Provider::Anthropic),
+            "ollama" => Some(Provider::Ollama),
+            _ => None,
+        }
+    }
+
+    // Provider selected by PROVIDER, defaulting to OpenAI
+    pub fn from_env() -> Provider {
+        match env::var("PROVIDER") {
+            Ok(name) => Provider::from_name(&name).unwrap_or_else(|| {
+                eprintln!("Unknown PROVIDER '{}', using openai", name);
+                Provider::OpenAI
+            }),
+            Err(_) => Provider::OpenAI,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Provider::OpenAI => "openai",
+            Provider::OpenRouter => "openrouter",
+            Provider::Anthropic => "anthropic",
+            Provider::Ollama => "ollama",
+        }
+    }
+
+    // OPENAI_BASE_URL points the OpenAI provider at any compatible server (llama.cpp, Azure,
+    // a proxy); a trailing slash is optional
+    pub fn base_url(&self) -> String {
+        let default = match self {
+            Provider::OpenAI => "https://api.openai.com/v1",
+            Provider::OpenRouter => "https://openrouter.ai/api/v1",
+            Provider::Anthropic => "https://api.anthropic.com/v1",
+            Provider::Ollama => "http://localhost:11434/v1",
+        };
+        let custom = match self {
+            Provider::OpenAI => env::var("OPENAI_BASE_URL").ok().filter(|url| !url.trim().is_empty()),
+            _ => None,
+        };
+        custom.as_deref().unwrap_or(default).trim().trim_end_matches('/').to_string()
+    }
+
+    pub fn chat_url(&self) -> String {
+        format!("{}/chat/completions", self.base_url())
+    }
+
+    // Model list endpoint, for providers whose listing works with plain bearer auth
+    pub fn models_url(&self) -> Option<String> {
+        match self {
+            Provider::Anthropic => None,
+            _ => Some(format!("{}/models", self.base_url())),
+        }
+    }
+
+    // Environment variable holding this provider's API key; Ollama runs locally without one
+    pub fn api_key_var(&self) -> Option<&'static str> {
+        match self {
+            Provider::OpenAI => Some("OPENAI_API_KEY"),
+            Provider::OpenRouter => Some("OPENROUTER_API_KEY"),
+            Provider::Anthropic => Some("ANTHROPIC_API_KEY"),
+            Provider::Ollama => None,
+        }
+    }
+
+    pub fn api_key(&self) -> Result<String, io::Error> {
+        let var = match self.api_key_var() {
+            Some(var) => var,
+            None => return Ok(String::new()),
+        };
+        env::var(var).ok().filter(|key| !key.trim().is_empty()).ok_or_else(|| {
+            io::Error::other(format!("{} is not set; it is required for the {} provider (add it to your environment or .env)", var, self.name()))
+        })
+    }
+}
+
+// Parse "provider:model, provider:model, ..." into an ordered chain. Entries with an unknown
+// provider or no model are skipped with a warning.
+pub fn parse_chain(spec: &str) -> Vec<(Provider, String)> {
+    let mut chain = Vec::new();
+    for entry in spec.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        match entry.split_once(':') {
+            Some((name, model)) if !model.trim().is_empty() => match Provider::from_name(name.trim()) {
+                Some(provider) => chain.push((provider, model.trim().to_string())),
+                None => eprintln!("Ignoring fallback entry '{}': unknown provider '{}'", entry, name.trim()),
+            },
+            _ => eprintln!("Ignoring fallback entry '{}': expected provider:model", entry),
+        }
+    }
+    chain
+}
+
+// Fallback chain from FALLBACK_CHAIN, e.g. "openrouter:openai/gpt-4o-mini,ollama:llama3"
+pub fn fallback_chain() -> Vec<(Provider, String)> {
+    env::var("FALLBACK_CHAIN").map(|spec| parse_chain(&spec)).unwrap_or_default()
+}
@@ -0,0 +1,254 @@
+use crate::audit;
+use crate::chat;
+use crate::files;
+use crate::http;
+use crate::provider::Provider;
+use crate::storage;
+use serde_json::{json, Value};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const INDEX_PATH: &str = "conversations/.recall_index.json";
+const EMBEDDING_MODEL: &str = "text-embedding-3-small";
+const EMBEDDING_BATCH_SIZE: usize = 100;
+
+// Upper bounds on how much recalled text gets injected into a single request
+pub const MAX_SNIPPETS: usize = 3;
+const MAX_SNIPPET_CHARS: usize = 500;
+const MIN_SIMILARITY: f64 = 0.3;
+
+pub struct RecallEntry {
+    pub session: String,
+    pub turn: usize,
+    pub role: String,
+    pub content: String,
+    pub embedding: Vec<f64>,
+}
+
+#[derive(Default)]
+pub struct RecallIndex {
+    entries: Vec<RecallEntry>,
+}
+
+impl RecallIndex {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    // Load the embeddings index from disk, starting empty if it is missing or unreadable
+    pub fn load() -> RecallIndex {
+        let entries = fs::read_to_string(INDEX_PATH)
+            .ok()
+            .and_then(|data| serde_json::from_str::<Value>(&data).ok())
+            .and_then(|value| value.as_array().cloned())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(entry_from_json)
+            .collect();
+        RecallIndex { entries }
+    }
+
+    pub fn exists_on_disk() -> bool {
+        Path::new(INDEX_PATH).exists()
+    }
+
+    pub fn save(&self) -> Result<(), io::Error> {
+        fs::create_dir_all(storage::SESSIONS_DIR)?;
+        let entries: Vec<Value> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                json!({
+                    "session": entry.session,
+                    "turn": entry.turn,
+                    "role": entry.role,
+                    "content": entry.content,
+                    "embedding": entry.embedding,
+                })
+            })
+            .collect();
+        files::write(Path::new(INDEX_PATH), &serde_json::to_string(&entries)?)
+    }
+
+    // Bring the index in line with the saved sessions, only embedding messages it hasn't seen.
+    // Returns the number of newly embedded messages.
+    pub async fn rebuild(&mut self, verbose: bool) -> Result<usize, Box<dyn std::error::Error>> {
+        let sessions = read_saved_sessions()?;
+
+        // Drop entries whose session was deleted or whose message changed
+        self.entries.retain(|entry| {
+            sessions.iter().any(|(name, messages)| {
+                name == &entry.session
+                    && messages.get(entry.turn).is_some_and(|(role, content)| {
+                        role == &entry.role && content == &entry.content
+                    })
+            })
+        });
+
+        let mut pending = Vec::new();
+        for (name, messages) in &sessions {
+            for (turn, (role, content)) in messages.iter().enumerate() {
+                if role == "system" || content.trim().is_empty() {
+                    continue;
+                }
+                let indexed = self
+                    .entries
+                    .iter()
+                    .any(|entry| &entry.session == name && entry.turn == turn);
+                if !indexed {
+                    pending.push((name.clone(), turn, role.clone(), content.clone()));
+                }
+            }
+        }
+
+        if verbose {
+            println!("Recall index: {} messages to embed", pending.len());
+        }
+
+        for batch in pending.chunks(EMBEDDING_BATCH_SIZE) {
+            let texts: Vec<String> = batch.iter().map(|(_, _, _, content)| content.clone()).collect();
+            let embeddings = embed(&texts).await?;
+            for ((session, turn, role, content), embedding) in batch.iter().cloned().zip(embeddings) {
+                self.entries.push(RecallEntry { session, turn, role, content, embedding });
+            }
+        }
+
+        self.save()?;
+        Ok(pending.len())
+    }
+
+    // Keep the index in step with sessions renamed or deleted in the store
+    pub fn rename_session(&mut self, old: &str, new: &str) {
+        for entry in self.entries.iter_mut().filter(|entry| entry.session == old) {
+            entry.session = new.to_string();
+        }
+    }
+
+    pub fn remove_session(&mut self, name: &str) {
+        self.entries.retain(|entry| entry.session != name);
+    }
+
+    // Most similar entries to the query embedding, best first
+    pub fn search(&self, query: &[f64], limit: usize) -> Vec<&RecallEntry> {
+        let mut scored: Vec<(f64, &RecallEntry)> = self
+            .entries
+            .iter()
+            .map(|entry| (cosine_similarity(query, &entry.embedding), entry))
+            .filter(|(score, _)| *score >= MIN_SIMILARITY)
+            .collect();
+        scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+        scored.into_iter().take(limit).map(|(_, entry)| entry).collect()
+    }
+}
+
+fn entry_from_json(value: &Value) -> Option<RecallEntry> {
+    Some(RecallEntry {
+        session: value["session"].as_str()?.to_string(),
+        turn: value["turn"].as_u64()? as usize,
+        role: value["role"].as_str()?.to_string(),
+        content: value["content"].as_str()?.to_string(),
+        embedding: value["embedding"].as_array()?.iter().filter_map(Value::as_f64).collect(),
+    })
+}
+
+// A saved session as its name and (role, content) pairs
+type SavedSession = (String, Vec<(String, String)>);
+
+fn read_saved_sessions() -> Result<Vec<SavedSession>, Box<dyn std::error::Error>> {
+    let store = storage::open_store()?;
+    let mut sessions = Vec::new();
+    for info in store.list()? {
+        let messages = store
+            .load(&info.name)?
+            .unwrap_or_default()
+            .iter()
+            .map(|message| {
+                (
+                    message["role"].as_str().unwrap_or_default().to_string(),
+                    message["content"].as_str().unwrap_or_default().to_string(),
+                )
+            })
+            .collect();
+        sessions.push((info.name, messages));
+    }
+    Ok(sessions)
+}
+
+pub async fn embed(texts: &[String]) -> Result<Vec<Vec<f64>>, Box<dyn std::error::Error>> {
+    if chat::offline() {
+        return Err(Box::new(io::Error::other("embeddings aren't available in offline mode")));
+    }
+    // Embeddings always come from OpenAI, whichever provider is used for chat
+    let api_key = Provider::OpenAI.api_key()?;
+    let client = http::client();
+
+    let url = format!("{}/embeddings", Provider::OpenAI.base_url());
+    let body = json!({
+        "model": EMBEDDING_MODEL,
+        "input": texts,
+    });
+
+    audit::record_request(&url, &api_key, &body);
+
+    let request = client.post(&url)
+        .header("Content-Type", "application/json")
+        .header("Authorization", format!("Bearer {}", api_key))
+        .json(&body);
+    let response = http::limited(request).send().await?;
+
+    if !response.status().is_success() {
+        let error_message = response.text().await?;
+        return Err(Box::new(io::Error::other(format!("Embedding call failed: {}", error_message))));
+    }
+
+    let res: Value = response.json().await?;
+    let embeddings = res["data"]
+        .as_array()
+        .map(|data| {
+            data.iter()
+                .map(|item| item["embedding"].as_array().map(|values| values.iter().filter_map(Value::as_f64).collect()).unwrap_or_default())
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok(embeddings)
+}
+
+pub fn cosine_similarity(a: &[f64], b: &[f64]) -> f64 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let dot: f64 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f64>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
+
+// Build the transient system message carrying the most relevant past snippets for this input
+pub async fn recall_context(index: &RecallIndex, user_input: &str) -> Result<Option<Value>, Box<dyn std::error::Error>> {
+    if index.len() == 0 || user_input.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let query = embed(&[user_input.to_string()]).await?;
+    let query = match query.first() {
+        Some(query) => query,
+        None => return Ok(None),
+    };
+
+    let snippets = index.search(query, MAX_SNIPPETS);
+    if snippets.is_empty() {
+        return Ok(None);
+    }
+
+    let mut context = String::from("Relevant excerpts from past conversations (for context only):\n");
+    for snippet in snippets {
+        let content: String = snippet.content.chars().take(MAX_SNIPPET_CHARS).collect();
+        context.push_str(&format!("\n[{} #{}] {}: {}\n", snippet.session, snippet.turn, snippet.role, content));
+    }
+    Ok(Some(json!({"role": "system", "content": context})))
+}
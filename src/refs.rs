@@ -0,0 +1,64 @@
+use crate::images;
+use serde_json::Value;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+// Conversation not yet saved under a name
+const UNSAVED: &str = "unsaved";
+
+// FNV-1a, chosen because it's stable across builds and platforms, unlike std's hasher
+fn content_hash(text: &str) -> String {
+    let hash = text.bytes().fold(0x811c_9dc5u32, |hash, byte| (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193));
+    format!("{:08x}", hash)
+}
+
+// A reference to a message, e.g. "notes@12#1a2b3c4d": session, message index, content hash
+pub fn make_ref(session: Option<&str>, index: usize, message: &Value) -> String {
+    format!("{}@{}#{}", session.unwrap_or(UNSAVED), index, content_hash(&images::text_of(&message["content"])))
+}
+
+pub struct MessageRef {
+    // None for a conversation that was never saved
+    pub session: Option<String>,
+    pub index: usize,
+    pub hash: String,
+}
+
+pub fn parse_ref(reference: &str) -> Option<MessageRef> {
+    let (session, rest) = reference.rsplit_once('@')?;
+    let (index, hash) = rest.split_once('#')?;
+    Some(MessageRef {
+        session: if session == UNSAVED { None } else { Some(session.to_string()) },
+        index: index.parse().ok()?,
+        hash: hash.to_string(),
+    })
+}
+
+// Whether a message still has the content the reference was made from
+pub fn matches(reference: &MessageRef, message: &Value) -> bool {
+    content_hash(&images::text_of(&message["content"])) == reference.hash
+}
+
+// Copy text with the first clipboard tool that is available on this system
+pub fn copy_to_clipboard(text: &str) -> Result<&'static str, io::Error> {
+    let candidates: &[(&'static str, &[&str])] = if cfg!(windows) {
+        &[("clip", &[])]
+    } else if cfg!(target_os = "macos") {
+        &[("pbcopy", &[])]
+    } else {
+        &[("wl-copy", &[]), ("xclip", &["-selection", "clipboard"]), ("xsel", &["--clipboard", "--input"])]
+    };
+    for (program, args) in candidates {
+        let mut child = match Command::new(program).args(*args).stdin(Stdio::piped()).spawn() {
+            Ok(child) => child,
+            Err(_) => continue,
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        if child.wait()?.success() {
+            return Ok(program);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, "no clipboard tool found (tried wl-copy, xclip, xsel, pbcopy, clip)"))
+}
@@ -0,0 +1,342 @@
+// Display renderers for assistant output. The conversation log always keeps the original
+// text; these only affect what is printed to the terminal.
+
+const BOLD: &str = "\x1b[1m";
+const BOLD_OFF: &str = "\x1b[22m";
+const DIM: &str = "\x1b[2m";
+const ITALIC: &str = "\x1b[3m";
+const ITALIC_OFF: &str = "\x1b[23m";
+const UNDERLINE: &str = "\x1b[4m";
+const UNDERLINE_OFF: &str = "\x1b[24m";
+const CYAN: &str = "\x1b[36m";
+const COLOR_OFF: &str = "\x1b[39m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    // Markdown syntax stripped, no styling
+    Plain,
+    // Markdown interpreted and styled with ANSI codes
+    Markdown,
+    // Exactly what the model returned
+    Raw,
+}
+
+impl OutputFormat {
+    pub fn parse(name: &str) -> Option<OutputFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "plain" => Some(OutputFormat::Plain),
+            "markdown" | "md" => Some(OutputFormat::Markdown),
+            "raw" => Some(OutputFormat::Raw),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Plain => "plain",
+            OutputFormat::Markdown => "markdown",
+            OutputFormat::Raw => "raw",
+        }
+    }
+}
+
+// Remove ANSI escape sequences (CSI styling, OSC titles/links and two-byte escapes) so text
+// can be stored or written to files without terminal codes
+pub fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI: parameters and intermediates up to a final byte in '@'..='~'
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // OSC: terminated by BEL or ESC '\'
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1b' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+// Line-at-a-time renderer. It only needs to remember whether it is inside a code fence,
+// so it works the same for whole responses and for lines completed while streaming.
+pub struct Renderer {
+    format: OutputFormat,
+    in_code_block: bool,
+}
+
+impl Renderer {
+    pub fn new(format: OutputFormat) -> Renderer {
+        Renderer { format, in_code_block: false }
+    }
+
+    // Render a single line (without its trailing newline)
+    pub fn render_line(&mut self, line: &str) -> String {
+        let styled = match self.format {
+            OutputFormat::Raw => return line.to_string(),
+            OutputFormat::Markdown => true,
+            OutputFormat::Plain => false,
+        };
+
+        let trimmed = line.trim_start();
+        if let Some(language) = trimmed.strip_prefix("```") {
+            self.in_code_block = !self.in_code_block;
+            return match (styled, self.in_code_block) {
+                (true, true) => format!("{}┌─ {}{}", DIM, language.trim(), RESET),
+                (true, false) => format!("{}└─{}", DIM, RESET),
+                (false, _) => String::new(),
+            };
+        }
+
+        if self.in_code_block {
+            return if styled {
+                format!("{}│{} {}{}{}", DIM, RESET, CYAN, line, COLOR_OFF)
+            } else {
+                format!("    {}", line)
+            };
+        }
+
+        let indent = &line[..line.len() - trimmed.len()];
+
+        // Headers
+        let level = trimmed.chars().take_while(|&c| c == '#').count();
+        if (1..=6).contains(&level) && trimmed[level..].starts_with(' ') {
+            let text = render_inline(trimmed[level..].trim(), styled);
+            return match (styled, level) {
+                (true, 1) => format!("{}{}{}{}{}", BOLD, UNDERLINE, text, UNDERLINE_OFF, BOLD_OFF),
+                (true, _) => format!("{}{}{}", BOLD, text, BOLD_OFF),
+                (false, _) => text,
+            };
+        }
+
+        // Horizontal rules
+        if trimmed.len() >= 3 && (trimmed.chars().all(|c| c == '-') || trimmed.chars().all(|c| c == '*')) {
+            return if styled { format!("{}{}{}", DIM, "─".repeat(40), RESET) } else { "─".repeat(40) };
+        }
+
+        // Bullet lists
+        for marker in ["- ", "* ", "+ "] {
+            if let Some(item) = trimmed.strip_prefix(marker) {
+                return format!("{}• {}", indent, render_inline(item, styled));
+            }
+        }
+
+        // Block quotes
+        if let Some(quote) = trimmed.strip_prefix('>') {
+            let text = render_inline(quote.trim_start(), styled);
+            return if styled { format!("{}{}│ {}{}{}", indent, DIM, ITALIC, text, RESET) } else { format!("{}| {}", indent, text) };
+        }
+
+        format!("{}{}", indent, render_inline(trimmed, styled))
+    }
+
+    // Render a complete block of text
+    pub fn render(&mut self, text: &str) -> String {
+        if self.format == OutputFormat::Raw {
+            return text.to_string();
+        }
+        let mut out: Vec<String> = text.split('\n').map(|line| self.render_line(line.trim_end_matches('\r'))).collect();
+        // An unterminated fence still gets its closing marker so styling never leaks
+        if self.in_code_block && self.format == OutputFormat::Markdown {
+            out.push(self.render_line("```"));
+        }
+        self.in_code_block = false;
+        out.join("\n")
+    }
+}
+
+// Emphasis and inline code within a line. Unmatched markers are printed literally.
+fn render_inline(text: &str, styled: bool) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let rest = &chars[i..];
+
+        if rest[0] == '`' {
+            if let Some(end) = find_closing(&chars, i + 1, &['`']) {
+                let code: String = chars[i + 1..end].iter().collect();
+                if styled {
+                    out.push_str(&format!("{}{}{}", CYAN, code, COLOR_OFF));
+                } else {
+                    out.push_str(&code);
+                }
+                i = end + 1;
+                continue;
+            }
+        }
+
+        if rest.len() > 2 && ((rest[0] == '*' && rest[1] == '*') || (rest[0] == '_' && rest[1] == '_')) {
+            let marker = [rest[0], rest[1]];
+            if let Some(end) = find_closing(&chars, i + 2, &marker) {
+                let inner: String = chars[i + 2..end].iter().collect();
+                let inner = render_inline(&inner, styled);
+                if styled {
+                    out.push_str(&format!("{}{}{}", BOLD, inner, BOLD_OFF));
+                } else {
+                    out.push_str(&inner);
+                }
+                i = end + 2;
+                continue;
+            }
+        }
+
+        // Single '*' only, so snake_case identifiers are left alone
+        if rest[0] == '*' && rest.len() > 1 && !rest[1].is_whitespace() {
+            if let Some(end) = find_closing(&chars, i + 1, &['*']) {
+                let inner: String = chars[i + 1..end].iter().collect();
+                let inner = render_inline(&inner, styled);
+                if styled {
+                    out.push_str(&format!("{}{}{}", ITALIC, inner, ITALIC_OFF));
+                } else {
+                    out.push_str(&inner);
+                }
+                i = end + 1;
+                continue;
+            }
+        }
+
+        out.push(rest[0]);
+        i += 1;
+    }
+    out
+}
+
+// Index of the next occurrence of `marker` at or after `start`, if it closes a non-empty span
+fn find_closing(chars: &[char], start: usize, marker: &[char]) -> Option<usize> {
+    if start + marker.len() > chars.len() {
+        return None;
+    }
+    (start..=chars.len() - marker.len())
+        .find(|&index| chars[index..index + marker.len()] == *marker)
+        .filter(|&index| index > start)
+}
+
+// Two texts next to each other in columns that together fit `width`, each under its title.
+// Lines wrap at spaces where they can; widths are counted in characters, so the texts should be
+// unstyled.
+pub fn side_by_side(left: (&str, &str), right: (&str, &str), width: usize) -> String {
+    const GUTTER: &str = " │ ";
+    let column = (width.saturating_sub(GUTTER.chars().count()) / 2).max(20);
+    let mut left_lines = vec![left.0.to_string(), "─".repeat(column)];
+    left_lines.extend(wrap(left.1, column));
+    let mut right_lines = vec![right.0.to_string(), "─".repeat(column)];
+    right_lines.extend(wrap(right.1, column));
+
+    let rows = left_lines.len().max(right_lines.len());
+    let mut out = Vec::with_capacity(rows);
+    for row in 0..rows {
+        let left = left_lines.get(row).map(String::as_str).unwrap_or_default();
+        let right = right_lines.get(row).map(String::as_str).unwrap_or_default();
+        let padding = column.saturating_sub(left.chars().count());
+        out.push(format!("{}{}{}{}", left, " ".repeat(padding), GUTTER, right).trim_end().to_string());
+    }
+    out.join("\n")
+}
+
+// Break text into lines of at most `width` characters, at spaces where possible
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split(' ') {
+            let mut word: Vec<char> = word.chars().collect();
+            // A word longer than the column is split wherever the column ends
+            while word.len() > width {
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                lines.push(word.drain(..width).collect());
+            }
+            let word: String = word.into_iter().collect();
+            let line_len = line.chars().count();
+            if line_len > 0 && line_len + 1 + word.chars().count() > width {
+                lines.push(std::mem::take(&mut line));
+            } else if line_len > 0 {
+                line.push(' ');
+            }
+            line.push_str(&word);
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+// Renders streamed fragments as complete lines become available. Raw output passes
+// straight through; the other formats hold back the current partial line.
+pub struct StreamRenderer {
+    renderer: Renderer,
+    pending: String,
+}
+
+impl StreamRenderer {
+    pub fn new(format: OutputFormat) -> StreamRenderer {
+        StreamRenderer { renderer: Renderer::new(format), pending: String::new() }
+    }
+
+    pub fn push(&mut self, fragment: &str) -> String {
+        if self.renderer.format == OutputFormat::Raw {
+            return fragment.to_string();
+        }
+        self.pending.push_str(fragment);
+        let mut out = String::new();
+        while let Some(newline) = self.pending.find('\n') {
+            let line: String = self.pending.drain(..=newline).collect();
+            out.push_str(&self.renderer.render_line(line.trim_end_matches(['\r', '\n'])));
+            out.push('\n');
+        }
+        out
+    }
+
+    // Flush whatever partial line remains at the end of the stream
+    pub fn finish(&mut self) -> String {
+        let rest: String = self.pending.drain(..).collect();
+        if self.renderer.format == OutputFormat::Raw {
+            return rest;
+        }
+        let mut out = if rest.is_empty() { String::new() } else { self.renderer.render_line(&rest) };
+        if self.renderer.in_code_block && self.renderer.format == OutputFormat::Markdown {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            out.push_str(&self.renderer.render_line("```"));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn side_by_side_wraps_each_column() {
+        let laid_out = side_by_side(("old", "one two three four five six"), ("new", "short"), 43);
+        let lines: Vec<&str> = laid_out.lines().collect();
+        assert_eq!(lines[0], format!("old{} │ new", " ".repeat(17)));
+        assert_eq!(lines[2], format!("one two three four{} │ short", " ".repeat(2)));
+        assert_eq!(lines[3], format!("five six{} │", " ".repeat(12)));
+        assert!(lines.iter().all(|line| line.chars().count() <= 43));
+    }
+}
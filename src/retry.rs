@@ -0,0 +1,83 @@
+use crate::http;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use std::env;
+use std::error::Error;
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const BASE_DELAY: Duration = Duration::from_millis(500);
+// Upper bound on any single wait, including one asked for through Retry-After
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+// API_MAX_RETRIES, 3 by default; 0 disables retrying
+pub fn max_retries() -> u32 {
+    env::var("API_MAX_RETRIES").ok().and_then(|value| value.parse().ok()).unwrap_or(DEFAULT_MAX_RETRIES)
+}
+
+// Rate limits and transient server failures are worth another try; anything else (bad request,
+// auth) will fail the same way again
+fn is_retryable(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 429 | 500 | 502 | 503 | 504)
+}
+
+// A connection that couldn't be made or a request that timed out may well succeed next time
+fn is_transient(err: &reqwest::Error) -> bool {
+    err.is_connect() || err.is_timeout()
+}
+
+// Retry-After in seconds, when the server sent one
+fn retry_after(response: &Response) -> Option<Duration> {
+    let seconds: f64 = response.headers().get("retry-after")?.to_str().ok()?.trim().parse().ok()?;
+    Some(Duration::from_secs_f64(seconds.max(0.0)))
+}
+
+// Exponential backoff with up to 50% random jitter, so clients that failed together don't
+// retry together
+fn backoff(attempt: u32) -> Duration {
+    let delay = BASE_DELAY.saturating_mul(2u32.saturating_pow(attempt));
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.subsec_nanos()).unwrap_or(0);
+    delay.mul_f64(1.0 + f64::from(nanos % 1000) / 2000.0)
+}
+
+// Send a request, retrying up to `max_retries` times while the status is retryable or the
+// request failed to connect or timed out. Each attempt gets `timeout` to produce a response.
+// The last response or error is returned as-is, so callers still see (and report) a final
+// failure.
+pub async fn send(request: RequestBuilder, max_retries: u32, timeout: Option<Duration>) -> Result<Response, Box<dyn Error>> {
+    let mut attempt = 0;
+    loop {
+        let send = match request.try_clone() {
+            Some(request) => request.send(),
+            None => return Err(Box::new(io::Error::other("the request body can't be resent, so the request can't be retried"))),
+        };
+        let result = match timeout {
+            Some(limit) => match tokio::time::timeout(limit, send).await {
+                Ok(result) => result.map_err(Failure::Request),
+                Err(_) => Err(Failure::TimedOut(limit)),
+            },
+            None => send.await.map_err(Failure::Request),
+        };
+        let (reason, delay) = match result {
+            Ok(response) if attempt >= max_retries || !is_retryable(response.status()) => return Ok(response),
+            Ok(response) => {
+                let delay = retry_after(&response).unwrap_or_else(|| backoff(attempt));
+                (response.status().to_string(), delay)
+            }
+            Err(Failure::Request(err)) if attempt >= max_retries || !is_transient(&err) => return Err(Box::new(err)),
+            Err(Failure::TimedOut(limit)) if attempt >= max_retries => return Err(Box::new(http::timed_out(limit))),
+            Err(Failure::Request(err)) => (if err.is_timeout() { "timed out" } else { "couldn't connect" }.to_string(), backoff(attempt)),
+            Err(Failure::TimedOut(_)) => ("timed out".to_string(), backoff(attempt)),
+        };
+        let delay = delay.min(MAX_DELAY);
+        attempt += 1;
+        eprintln!("\r\x1b[KRequest failed ({}); retrying in {:.1}s ({}/{})...", reason, delay.as_secs_f64(), attempt, max_retries);
+        tokio::time::sleep(delay).await;
+    }
+}
+
+// Why an attempt produced no response
+enum Failure {
+    Request(reqwest::Error),
+    TimedOut(Duration),
+}
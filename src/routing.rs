@@ -0,0 +1,29 @@
+use crate::input;
+use std::env;
+
+// Messages at or under this many (estimated) tokens count as short
+const DEFAULT_MAX_TOKENS: usize = 30;
+// Words suggesting a question needs the full model however short it is
+const DEFAULT_COMPLEX_KEYWORDS: &[&str] = &["explain", "why", "how", "code", "debug", "analyze", "analyse", "compare", "design", "implement", "refactor", "prove", "write", "step by step"];
+
+// Opt-in cost saving: with CHEAP_MODEL set, short messages without any complex keywords are
+// answered by that model. DOWNGRADE_MAX_TOKENS and DOWNGRADE_KEYWORDS (comma-separated)
+// tune the heuristic. Returns the cheap model when this message qualifies.
+pub fn cheap_model_for(message: &str) -> Option<String> {
+    let cheap_model = env::var("CHEAP_MODEL").ok().filter(|model| !model.trim().is_empty())?;
+    let max_tokens = env::var("DOWNGRADE_MAX_TOKENS").ok().and_then(|value| value.trim().parse().ok()).unwrap_or(DEFAULT_MAX_TOKENS);
+    if message.trim().is_empty() || input::estimate_tokens(message) > max_tokens {
+        return None;
+    }
+
+    let message = message.to_lowercase();
+    let is_complex = match env::var("DOWNGRADE_KEYWORDS") {
+        Ok(keywords) => keywords.split(',').map(str::trim).filter(|keyword| !keyword.is_empty()).any(|keyword| message.contains(&keyword.to_lowercase())),
+        Err(_) => DEFAULT_COMPLEX_KEYWORDS.iter().any(|keyword| message.contains(keyword)),
+    };
+    if is_complex {
+        None
+    } else {
+        Some(cheap_model.trim().to_string())
+    }
+}
@@ -0,0 +1,71 @@
+use jsonschema::Validator;
+use serde_json::{json, Value};
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// A JSON schema that responses are asked to follow (response_format json_schema) and are
+// checked against once they arrive
+pub struct Schema {
+    pub path: String,
+    name: String,
+    schema: Value,
+    validator: Validator,
+}
+
+impl Schema {
+    // Load and compile a schema file, so a broken schema is reported before anything is sent
+    pub fn load(path: &str) -> Result<Schema, String> {
+        let data = fs::read_to_string(path).map_err(|err| format!("failed to read {}: {}", path, err))?;
+        let schema: Value = serde_json::from_str(&data).map_err(|err| format!("{} is not valid JSON: {}", path, err))?;
+        let validator = jsonschema::validator_for(&schema).map_err(|err| format!("{} is not a valid JSON schema: {}", path, err))?;
+        Ok(Schema { path: path.to_string(), name: schema_name(path), schema, validator })
+    }
+
+    // response_format value for the request body. Strict mode makes the API enforce the schema.
+    pub fn response_format(&self) -> Value {
+        json!({
+            "type": "json_schema",
+            "json_schema": {"name": self.name, "schema": self.schema, "strict": true},
+        })
+    }
+
+    // Problems with a response, empty when it parses and matches the schema
+    pub fn validate(&self, content: &str) -> Vec<String> {
+        let instance: Value = match serde_json::from_str(content.trim()) {
+            Ok(instance) => instance,
+            Err(err) => return vec![format!("response is not valid JSON: {}", err)],
+        };
+        self.validator.iter_errors(&instance).map(|err| {
+            let location = err.instance_path().to_string();
+            if location.is_empty() {
+                err.to_string()
+            } else {
+                format!("{}: {}", location, err)
+            }
+        }).collect()
+    }
+}
+
+// The API only accepts [a-zA-Z0-9_-] in schema names, so derive one from the file name
+fn schema_name(path: &str) -> String {
+    let stem = Path::new(path).file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+    let name: String = stem.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect();
+    if name.is_empty() {
+        "response".to_string()
+    } else {
+        name
+    }
+}
+
+// Schema named by RESPONSE_SCHEMA, if set. A schema that fails to load is reported and skipped.
+pub fn from_env() -> Option<Schema> {
+    let path = env::var("RESPONSE_SCHEMA").ok().filter(|path| !path.trim().is_empty())?;
+    match Schema::load(path.trim()) {
+        Ok(schema) => Some(schema),
+        Err(err) => {
+            eprintln!("Ignoring RESPONSE_SCHEMA: {}", err);
+            None
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use regex::{Regex, RegexBuilder};
+
+// Characters of context shown either side of a match
+const SNIPPET_CONTEXT: usize = 40;
+
+// What to look for across stored sessions. Plain terms are matched literally (and can use
+// the SQLite full-text index); patterns are regular expressions.
+pub struct Matcher {
+    pub term: String,
+    pub is_pattern: bool,
+    pub case_sensitive: bool,
+    regex: Regex,
+}
+
+impl Matcher {
+    pub fn new(term: &str, is_pattern: bool, case_sensitive: bool) -> Result<Matcher, regex::Error> {
+        let source = if is_pattern { term.to_string() } else { regex::escape(term) };
+        let regex = RegexBuilder::new(&source).case_insensitive(!case_sensitive).build()?;
+        Ok(Matcher { term: term.to_string(), is_pattern, case_sensitive, regex })
+    }
+
+    // Byte range of the first match in `text`
+    pub fn find(&self, text: &str) -> Option<(usize, usize)> {
+        self.regex.find(text).map(|found| (found.start(), found.end()))
+    }
+}
+
+pub struct SearchHit {
+    pub session: String,
+    pub turn: usize,
+    pub role: String,
+    pub snippet: String,
+}
+
+// A single-line excerpt around a match, with the match itself bracketed
+pub fn snippet(text: &str, start: usize, end: usize) -> String {
+    let mut before: Vec<char> = text[..start].chars().rev().take(SNIPPET_CONTEXT).collect();
+    before.reverse();
+    let before: String = before.into_iter().collect();
+    let after: String = text[end..].chars().take(SNIPPET_CONTEXT).collect();
+    let prefix = if before.len() < start { "…" } else { "" };
+    let suffix = if after.len() < text.len() - end { "…" } else { "" };
+    format!("{}{}[{}]{}{}", prefix, before, &text[start..end], after, suffix).replace('\n', " ")
+}
+
+pub fn print_hits(hits: &[SearchHit]) {
+    if hits.is_empty() {
+        println!("No matches.");
+        return;
+    }
+    for hit in hits {
+        println!("{} #{} ({}): {}", hit.session, hit.turn, hit.role, hit.snippet);
+    }
+    println!("{} match{}.", hits.len(), if hits.len() == 1 { "" } else { "es" });
+}
+
+// Parse "[-r|--regex] [-c|--case-sensitive] <term...>" into a matcher
+pub fn parse_query(args: &str) -> Result<Matcher, String> {
+    let mut is_pattern = false;
+    let mut case_sensitive = false;
+    let mut rest = args.trim();
+    loop {
+        let (flag, remainder) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        match flag {
+            "-r" | "--regex" => is_pattern = true,
+            "-c" | "--case-sensitive" => case_sensitive = true,
+            _ => break,
+        }
+        rest = remainder.trim_start();
+    }
+    if rest.is_empty() {
+        return Err("missing search term".to_string());
+    }
+    Matcher::new(rest, is_pattern, case_sensitive).map_err(|err| format!("invalid regex: {}", err))
+}
@@ -0,0 +1,44 @@
+use regex::Regex;
+use std::env;
+
+// OpenAI-style secret keys, including project keys (sk-proj-...)
+const DEFAULT_KEY_PATTERN: &str = r"sk-[A-Za-z0-9_\-]{20,}";
+const REDACTED: &str = "[REDACTED]";
+
+#[derive(PartialEq)]
+pub enum KeyHandling {
+    Off,
+    // Ask before sending whether to redact
+    Warn,
+    // Redact without asking
+    Redact,
+}
+
+// SECRET_HANDLING=off|warn|redact, warning by default
+pub fn handling() -> KeyHandling {
+    match env::var("SECRET_HANDLING").unwrap_or_default().to_ascii_lowercase().as_str() {
+        "off" | "0" | "false" => KeyHandling::Off,
+        "redact" => KeyHandling::Redact,
+        _ => KeyHandling::Warn,
+    }
+}
+
+// Pattern from SECRET_PATTERN, falling back to the default if it is unset or invalid
+fn key_pattern() -> Regex {
+    if let Ok(pattern) = env::var("SECRET_PATTERN") {
+        match Regex::new(&pattern) {
+            Ok(regex) => return regex,
+            Err(err) => eprintln!("Ignoring invalid SECRET_PATTERN: {}", err),
+        }
+    }
+    Regex::new(DEFAULT_KEY_PATTERN).expect("default key pattern is valid")
+}
+
+// Number of likely API keys in the text
+pub fn count_keys(text: &str) -> usize {
+    key_pattern().find_iter(text).count()
+}
+
+pub fn redact_keys(text: &str) -> String {
+    key_pattern().replace_all(text, REDACTED).into_owned()
+}
@@ -0,0 +1,32 @@
+use std::env;
+use std::process::Command;
+
+// Running shell commands from the chat is disabled unless ALLOW_SHELL_COMMANDS is set
+pub fn shell_commands_allowed() -> bool {
+    env::var("ALLOW_SHELL_COMMANDS").map(|value| value == "1" || value.eq_ignore_ascii_case("true")).unwrap_or(false)
+}
+
+// Run a command through the platform shell and format its output as a labeled, fenced
+// block ready to be staged as context
+pub fn run_for_context(command: &str) -> Result<String, std::io::Error> {
+    let output = if cfg!(windows) {
+        Command::new("cmd").args(["/C", command]).output()?
+    } else {
+        Command::new("sh").args(["-c", command]).output()?
+    };
+
+    let mut body = String::from_utf8_lossy(&output.stdout).trim_end().to_string();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !stderr.trim().is_empty() {
+        if !body.is_empty() {
+            body.push('\n');
+        }
+        body.push_str(stderr.trim_end());
+    }
+
+    let status = match output.status.code() {
+        Some(code) => format!("exit status {}", code),
+        None => "terminated by signal".to_string(),
+    };
+    Ok(format!("Output of `{}` ({}):\n```text\n{}\n```", command, status, body))
+}
@@ -0,0 +1,147 @@
+use crate::memory;
+use crate::storage;
+use crate::ChatState;
+use crate::usage;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::Notify;
+
+// Session name used when the conversation was never saved under a name of its own
+const AUTOSAVE_NAME: &str = "autosave";
+
+// Signals seen by the listeners from `listen`, each cleared by whatever acts on it
+static TERMINATE: AtomicBool = AtomicBool::new(false);
+static INTERRUPT: AtomicBool = AtomicBool::new(false);
+static SIGNALLED: Notify = Notify::const_new();
+
+// Start one SIGTERM and one Ctrl-C listener for the rest of the run. Tokio's handler stays
+// installed once a listener exists, so signals that arrive while nothing is waiting on them
+// (a slash command, a summary, a confirm prompt) are recorded here rather than dropped.
+pub fn listen() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::spawn(async move {
+                    while terminate.recv().await.is_some() {
+                        raise(&TERMINATE);
+                    }
+                });
+            }
+            Err(err) => eprintln!("Failed to install SIGTERM handler: {}", err),
+        }
+        match signal(SignalKind::interrupt()) {
+            Ok(mut interrupt) => {
+                tokio::spawn(async move {
+                    while interrupt.recv().await.is_some() {
+                        on_interrupt();
+                    }
+                });
+            }
+            Err(err) => eprintln!("Failed to install Ctrl-C handler: {}", err),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::spawn(async {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            on_interrupt();
+        }
+    });
+}
+
+// Whatever is running is left to finish; the chat ends once control is back in the loop
+fn on_interrupt() {
+    eprintln!("\n(Interrupted: finishing what's running, then exiting)");
+    raise(&INTERRUPT);
+}
+
+fn raise(flag: &AtomicBool) {
+    flag.store(true, Ordering::SeqCst);
+    SIGNALLED.notify_waiters();
+}
+
+// Whether SIGTERM arrived since it was last acted on; clears it
+pub fn take_terminate() -> bool {
+    TERMINATE.swap(false, Ordering::SeqCst)
+}
+
+// Whether Ctrl-C was pressed since it was last acted on; clears it
+pub fn take_interrupt() -> bool {
+    INTERRUPT.swap(false, Ordering::SeqCst)
+}
+
+// Resolves (and clears the signal) once SIGTERM has arrived, right away if it already has
+pub async fn terminate_signal() {
+    wait_for(take_terminate).await
+}
+
+// The same for Ctrl-C
+pub async fn interrupt_signal() {
+    wait_for(take_interrupt).await
+}
+
+async fn wait_for(take: fn() -> bool) {
+    loop {
+        // Registered before checking, so a signal in between still wakes it
+        let signalled = SIGNALLED.notified();
+        tokio::pin!(signalled);
+        signalled.as_mut().enable();
+        if take() {
+            return;
+        }
+        signalled.await;
+    }
+}
+
+// Everything that happens on the way out, whether the user quit or the process was told to
+// stop: the conversation is saved and the optional memory hook runs
+pub async fn run(state: &mut ChatState) {
+    // The crash journal is only discarded once its contents are safely stored
+    if save_conversation(state) {
+        state.wal.remove();
+    }
+
+    if memory::enabled() {
+        println!("Saving learnings from this session...");
+        // Another SIGTERM or Ctrl-C meanwhile skips it rather than holding up the exit
+        let learnings = tokio::select! {
+            learnings = memory::save_learnings(&state.conversation_log, state.provider, &state.model, state.verbose) => Some(learnings),
+            _ = terminate_signal() => None,
+            _ = interrupt_signal() => None,
+        };
+        match learnings {
+            Some(Ok(Some(path))) => println!("Learnings appended to {}", path),
+            Some(Ok(None)) => println!("Nothing new to remember."),
+            Some(Err(err)) => eprintln!("Failed to save learnings: {}", err),
+            None => println!("Skipped saving learnings."),
+        }
+    }
+
+    println!("Goodbye!");
+    let _ = io::stdout().flush();
+}
+
+// Save to the active session, or to the autosave slot if it was never named, and record it as
+// the last session. A conversation with no user messages has nothing worth keeping. Returns
+// false if saving failed.
+fn save_conversation(state: &mut ChatState) -> bool {
+    if !state.conversation_log.iter().any(|message| message["role"] == "user") {
+        return true;
+    }
+    // Kept for --resume, independent of the named save below
+    if let Err(err) = storage::save_last_session(&state.conversation_log) {
+        eprintln!("Failed to write {}: {}", storage::LAST_SESSION_PATH, err);
+    }
+    let name = state.session_name.clone().unwrap_or_else(|| AUTOSAVE_NAME.to_string());
+    match storage::open_store().and_then(|store| store.save(&name, &state.conversation_log, &state.model, usage::session_totals(), &state.checkpoints)) {
+        Ok(()) => {
+            println!("Conversation saved as '{}'.", name);
+            true
+        }
+        Err(err) => {
+            eprintln!("Failed to save conversation as '{}': {}", name, err);
+            false
+        }
+    }
+}
@@ -0,0 +1,445 @@
+use crate::files;
+use crate::images;
+use crate::search::{self, Matcher, SearchHit};
+use crate::usage::Usage;
+use chrono::{DateTime, Local};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::{json, Value};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Saved sessions live here, as <name>.json files or in the SQLite database
+pub const SESSIONS_DIR: &str = "conversations";
+const DEFAULT_SQLITE_PATH: &str = "conversations/sessions.db";
+// The conversation as it stood at the last exit, whichever backend is in use. It has the same
+// format as a file-backend session, so it can also be opened with /load last_session.
+pub const LAST_SESSION_PATH: &str = "conversations/last_session.json";
+
+// Named snapshots of a conversation, saved along with it
+pub type Checkpoints = Vec<(String, Vec<Value>)>;
+
+pub struct SessionInfo {
+    pub name: String,
+    pub messages: usize,
+    pub updated_at: Option<DateTime<Local>>,
+}
+
+// A place conversations can be saved to and loaded from
+pub trait SessionStore {
+    // `usage` is the token usage and estimated cost so far, for backends that keep it
+    fn save(&self, name: &str, messages: &[Value], model: &str, usage: (Usage, f64), checkpoints: &[(String, Vec<Value>)]) -> Result<(), Box<dyn Error>>;
+    // None when no session with that name exists
+    fn load(&self, name: &str) -> Result<Option<Vec<Value>>, Box<dyn Error>>;
+    // The checkpoints saved with a session, in the order they were made
+    fn load_checkpoints(&self, name: &str) -> Result<Checkpoints, Box<dyn Error>>;
+    // The usage recorded with the last save, or None when the backend doesn't keep it
+    fn load_usage(&self, _name: &str) -> Result<Option<(Usage, f64)>, Box<dyn Error>> {
+        Ok(None)
+    }
+    fn list(&self) -> Result<Vec<SessionInfo>, Box<dyn Error>>;
+    // Both return false when the session doesn't exist
+    fn rename(&self, old: &str, new: &str) -> Result<bool, Box<dyn Error>>;
+    fn delete(&self, name: &str) -> Result<bool, Box<dyn Error>>;
+
+    fn exists(&self, name: &str) -> Result<bool, Box<dyn Error>> {
+        Ok(self.list()?.iter().any(|info| info.name == name))
+    }
+
+    // Every message matching the query
+    fn search(&self, matcher: &Matcher) -> Result<Vec<SearchHit>, Box<dyn Error>> {
+        scan_search(self, matcher)
+    }
+}
+
+// Search by loading and scanning each session in turn
+fn scan_search<S: SessionStore + ?Sized>(store: &S, matcher: &Matcher) -> Result<Vec<SearchHit>, Box<dyn Error>> {
+    let mut hits = Vec::new();
+    for info in store.list()? {
+        for (turn, message) in store.load(&info.name)?.unwrap_or_default().iter().enumerate() {
+            let content = images::text_of(&message["content"]);
+            if let Some((start, end)) = matcher.find(&content) {
+                hits.push(SearchHit {
+                    session: info.name.clone(),
+                    turn,
+                    role: message["role"].as_str().unwrap_or_default().to_string(),
+                    snippet: search::snippet(&content, start, end),
+                });
+            }
+        }
+    }
+    Ok(hits)
+}
+
+// Open the backend selected by STORAGE_BACKEND (file by default, or sqlite)
+pub fn open_store() -> Result<Box<dyn SessionStore>, Box<dyn Error>> {
+    match env::var("STORAGE_BACKEND").unwrap_or_default().to_ascii_lowercase().as_str() {
+        "" | "file" | "files" => Ok(Box::new(FileStore::new(SESSIONS_DIR))),
+        "sqlite" => {
+            let path = env::var("SQLITE_PATH").unwrap_or_else(|_| DEFAULT_SQLITE_PATH.to_string());
+            Ok(Box::new(SqliteStore::open(Path::new(&path))?))
+        }
+        other => Err(Box::new(io::Error::other(format!("unknown STORAGE_BACKEND: {}", other)))),
+    }
+}
+
+// Session names become file names, so keep them to a safe character set
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.starts_with('.') {
+        return Err("session names must not be empty or start with '.'".to_string());
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) {
+        return Err("session names may only contain letters, digits, '-', '_' and '.'".to_string());
+    }
+    Ok(())
+}
+
+// Name for a session saved without one, e.g. chat-20240131-142501
+pub fn timestamped_name() -> String {
+    Local::now().format("chat-%Y%m%d-%H%M%S").to_string()
+}
+
+pub fn save_last_session(messages: &[Value]) -> Result<(), Box<dyn Error>> {
+    files::write_atomic(Path::new(LAST_SESSION_PATH), &serde_json::to_string_pretty(messages)?)?;
+    Ok(())
+}
+
+// None when there is no last session yet
+pub fn load_last_session() -> Result<Option<Vec<Value>>, Box<dyn Error>> {
+    let data = match fs::read_to_string(LAST_SESSION_PATH) {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(Box::new(err)),
+    };
+    Ok(Some(serde_json::from_str(&data)?))
+}
+
+// One JSON array of messages per session. A session with checkpoints is instead an object
+// holding the messages and the checkpoints, so files without any keep the plain format.
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(dir: &str) -> FileStore {
+        FileStore { dir: PathBuf::from(dir) }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", name))
+    }
+
+    // The parsed session file, or None when there isn't one
+    fn read(&self, name: &str) -> Result<Option<Value>, Box<dyn Error>> {
+        let data = match fs::read_to_string(self.path(name)) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(Box::new(err)),
+        };
+        Ok(Some(serde_json::from_str(&data)?))
+    }
+}
+
+impl SessionStore for FileStore {
+    fn save(&self, name: &str, messages: &[Value], _model: &str, _usage: (Usage, f64), checkpoints: &[(String, Vec<Value>)]) -> Result<(), Box<dyn Error>> {
+        let session = if checkpoints.is_empty() {
+            json!(messages)
+        } else {
+            let checkpoints: Vec<Value> = checkpoints.iter().map(|(name, snapshot)| json!({"name": name, "messages": snapshot})).collect();
+            json!({"messages": messages, "checkpoints": checkpoints})
+        };
+        files::write_atomic(&self.path(name), &serde_json::to_string_pretty(&session)?)?;
+        Ok(())
+    }
+
+    fn load(&self, name: &str) -> Result<Option<Vec<Value>>, Box<dyn Error>> {
+        match self.read(name)? {
+            Some(Value::Object(mut session)) => Ok(Some(serde_json::from_value(session.remove("messages").unwrap_or_default())?)),
+            Some(messages) => Ok(Some(serde_json::from_value(messages)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn load_checkpoints(&self, name: &str) -> Result<Checkpoints, Box<dyn Error>> {
+        let checkpoints = match self.read(name)? {
+            Some(Value::Object(mut session)) => session.remove("checkpoints").unwrap_or_default(),
+            _ => return Ok(Vec::new()),
+        };
+        let mut loaded = Vec::new();
+        for checkpoint in checkpoints.as_array().into_iter().flatten() {
+            let name = checkpoint["name"].as_str().ok_or("checkpoint without a name")?.to_string();
+            loaded.push((name, serde_json::from_value(checkpoint["messages"].clone())?));
+        }
+        Ok(loaded)
+    }
+
+    fn list(&self) -> Result<Vec<SessionInfo>, Box<dyn Error>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut sessions = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) if !name.starts_with('.') => name.to_string(),
+                _ => continue,
+            };
+            let messages = self.load(&name).ok().flatten().map(|messages| messages.len()).unwrap_or(0);
+            let updated_at = fs::metadata(&path).and_then(|metadata| metadata.modified()).ok().map(DateTime::<Local>::from);
+            sessions.push(SessionInfo { name, messages, updated_at });
+        }
+        sessions.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(sessions)
+    }
+
+    fn rename(&self, old: &str, new: &str) -> Result<bool, Box<dyn Error>> {
+        let from = self.path(old);
+        if !from.exists() {
+            return Ok(false);
+        }
+        files::confirm_write(&self.path(new))?;
+        fs::rename(from, self.path(new))?;
+        Ok(true)
+    }
+
+    fn delete(&self, name: &str) -> Result<bool, Box<dyn Error>> {
+        files::confirm_write(&self.path(name))?;
+        match fs::remove_file(self.path(name)) {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(Box::new(err)),
+        }
+    }
+}
+
+// Sessions, their metadata, messages, token usage and checkpoints in a single SQLite database
+pub struct SqliteStore {
+    conn: Connection,
+    path: PathBuf,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> Result<SqliteStore, Box<dyn Error>> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "PRAGMA foreign_keys = ON;
+            CREATE TABLE IF NOT EXISTS sessions (
+                name TEXT PRIMARY KEY,
+                model TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS messages (
+                session TEXT NOT NULL REFERENCES sessions(name) ON DELETE CASCADE,
+                position INTEGER NOT NULL,
+                role TEXT NOT NULL,
+                message TEXT NOT NULL,
+                PRIMARY KEY (session, position)
+            );
+            CREATE TABLE IF NOT EXISTS usage (
+                session TEXT PRIMARY KEY REFERENCES sessions(name) ON DELETE CASCADE,
+                prompt_tokens INTEGER NOT NULL,
+                completion_tokens INTEGER NOT NULL,
+                total_tokens INTEGER NOT NULL,
+                cost REAL NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS checkpoints (
+                session TEXT NOT NULL REFERENCES sessions(name) ON DELETE CASCADE,
+                position INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                messages TEXT NOT NULL,
+                PRIMARY KEY (session, position)
+            );
+            CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(
+                session UNINDEXED,
+                position UNINDEXED,
+                role UNINDEXED,
+                content
+            );",
+        )?;
+
+        // Databases written before the full-text index existed get it populated once
+        let indexed: i64 = conn.query_row("SELECT COUNT(*) FROM messages_fts", [], |row| row.get(0))?;
+        if indexed == 0 {
+            conn.execute(
+                "INSERT INTO messages_fts (session, position, role, content)
+                 SELECT session, position, role, COALESCE(json_extract(message, '$.content'), '') FROM messages",
+                [],
+            )?;
+        }
+        Ok(SqliteStore { conn, path: path.to_path_buf() })
+    }
+}
+
+impl SessionStore for SqliteStore {
+    fn save(&self, name: &str, messages: &[Value], model: &str, usage: (Usage, f64), checkpoints: &[(String, Vec<Value>)]) -> Result<(), Box<dyn Error>> {
+        files::confirm_write(&self.path)?;
+        let now = Local::now().to_rfc3339();
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO sessions (name, model, created_at, updated_at) VALUES (?1, ?2, ?3, ?3)
+             ON CONFLICT(name) DO UPDATE SET model = excluded.model, updated_at = excluded.updated_at",
+            params![name, model, now],
+        )?;
+        tx.execute("DELETE FROM messages WHERE session = ?1", params![name])?;
+        tx.execute("DELETE FROM messages_fts WHERE session = ?1", params![name])?;
+        let (totals, cost) = usage;
+        tx.execute(
+            "INSERT INTO usage (session, prompt_tokens, completion_tokens, total_tokens, cost) VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(session) DO UPDATE SET prompt_tokens = excluded.prompt_tokens, completion_tokens = excluded.completion_tokens,
+                 total_tokens = excluded.total_tokens, cost = excluded.cost",
+            params![name, totals.prompt_tokens as i64, totals.completion_tokens as i64, totals.total_tokens as i64, cost],
+        )?;
+        {
+            let mut insert = tx.prepare("INSERT INTO messages (session, position, role, message) VALUES (?1, ?2, ?3, ?4)")?;
+            let mut index = tx.prepare("INSERT INTO messages_fts (session, position, role, content) VALUES (?1, ?2, ?3, ?4)")?;
+            for (position, message) in messages.iter().enumerate() {
+                let role = message["role"].as_str().unwrap_or_default();
+                // The whole message is kept as JSON so non-text content survives a round trip
+                insert.execute(params![name, position as i64, role, message.to_string()])?;
+                index.execute(params![name, position as i64, role, images::text_of(&message["content"])])?;
+            }
+        }
+        tx.execute("DELETE FROM checkpoints WHERE session = ?1", params![name])?;
+        {
+            let mut insert = tx.prepare("INSERT INTO checkpoints (session, position, name, messages) VALUES (?1, ?2, ?3, ?4)")?;
+            for (position, (checkpoint, snapshot)) in checkpoints.iter().enumerate() {
+                insert.execute(params![name, position as i64, checkpoint, serde_json::to_string(snapshot)?])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn load(&self, name: &str) -> Result<Option<Vec<Value>>, Box<dyn Error>> {
+        let exists = self
+            .conn
+            .query_row("SELECT 1 FROM sessions WHERE name = ?1", params![name], |_| Ok(()))
+            .optional()?
+            .is_some();
+        if !exists {
+            return Ok(None);
+        }
+
+        let mut query = self.conn.prepare("SELECT message FROM messages WHERE session = ?1 ORDER BY position")?;
+        let rows = query.query_map(params![name], |row| row.get::<_, String>(0))?;
+        let mut messages = Vec::new();
+        for row in rows {
+            messages.push(serde_json::from_str(&row?)?);
+        }
+        Ok(Some(messages))
+    }
+
+    fn load_checkpoints(&self, name: &str) -> Result<Checkpoints, Box<dyn Error>> {
+        let mut query = self.conn.prepare("SELECT name, messages FROM checkpoints WHERE session = ?1 ORDER BY position")?;
+        let rows = query.query_map(params![name], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+        let mut checkpoints = Vec::new();
+        for row in rows {
+            let (checkpoint, snapshot) = row?;
+            checkpoints.push((checkpoint, serde_json::from_str(&snapshot)?));
+        }
+        Ok(checkpoints)
+    }
+
+    fn load_usage(&self, name: &str) -> Result<Option<(Usage, f64)>, Box<dyn Error>> {
+        let row = self
+            .conn
+            .query_row("SELECT prompt_tokens, completion_tokens, total_tokens, cost FROM usage WHERE session = ?1", params![name], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?, row.get::<_, f64>(3)?))
+            })
+            .optional()?;
+        Ok(row.map(|(prompt, completion, total, cost)| {
+            (Usage { prompt_tokens: prompt as u64, completion_tokens: completion as u64, total_tokens: total as u64 }, cost)
+        }))
+    }
+
+    fn list(&self) -> Result<Vec<SessionInfo>, Box<dyn Error>> {
+        let mut query = self.conn.prepare(
+            "SELECT s.name, s.updated_at, COUNT(m.position) FROM sessions s
+             LEFT JOIN messages m ON m.session = s.name
+             GROUP BY s.name ORDER BY s.name",
+        )?;
+        let rows = query.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?))
+        })?;
+        let mut sessions = Vec::new();
+        for row in rows {
+            let (name, updated_at, messages) = row?;
+            sessions.push(SessionInfo {
+                name,
+                messages: messages as usize,
+                updated_at: DateTime::parse_from_rfc3339(&updated_at).ok().map(|time| time.with_timezone(&Local)),
+            });
+        }
+        Ok(sessions)
+    }
+
+    fn rename(&self, old: &str, new: &str) -> Result<bool, Box<dyn Error>> {
+        files::confirm_write(&self.path)?;
+        let tx = self.conn.unchecked_transaction()?;
+        // Copy the session row first so the messages' foreign key always points somewhere
+        let copied = tx.execute(
+            "INSERT INTO sessions (name, model, created_at, updated_at)
+             SELECT ?2, model, created_at, updated_at FROM sessions WHERE name = ?1",
+            params![old, new],
+        )?;
+        if copied == 0 {
+            return Ok(false);
+        }
+        tx.execute("UPDATE messages SET session = ?2 WHERE session = ?1", params![old, new])?;
+        tx.execute("UPDATE messages_fts SET session = ?2 WHERE session = ?1", params![old, new])?;
+        tx.execute("UPDATE usage SET session = ?2 WHERE session = ?1", params![old, new])?;
+        tx.execute("UPDATE checkpoints SET session = ?2 WHERE session = ?1", params![old, new])?;
+        tx.execute("DELETE FROM sessions WHERE name = ?1", params![old])?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    fn delete(&self, name: &str) -> Result<bool, Box<dyn Error>> {
+        files::confirm_write(&self.path)?;
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute("DELETE FROM messages_fts WHERE session = ?1", params![name])?;
+        tx.execute("DELETE FROM messages WHERE session = ?1", params![name])?;
+        tx.execute("DELETE FROM usage WHERE session = ?1", params![name])?;
+        tx.execute("DELETE FROM checkpoints WHERE session = ?1", params![name])?;
+        let deleted = tx.execute("DELETE FROM sessions WHERE name = ?1", params![name])?;
+        tx.commit()?;
+        Ok(deleted > 0)
+    }
+
+    // Plain case-insensitive terms go through the full-text index; regexes and
+    // case-sensitive searches fall back to scanning every message
+    fn search(&self, matcher: &Matcher) -> Result<Vec<SearchHit>, Box<dyn Error>> {
+        if matcher.is_pattern || matcher.case_sensitive {
+            return scan_search(self, matcher);
+        }
+
+        // Quote the term so FTS query syntax in it is taken literally
+        let query_text = format!("\"{}\"", matcher.term.replace('"', "\"\""));
+        let mut query = self.conn.prepare(
+            "SELECT session, position, role, snippet(messages_fts, 3, '[', ']', '…', 12) FROM messages_fts
+             WHERE messages_fts MATCH ?1 ORDER BY session, position",
+        )?;
+        let rows = query.query_map(params![query_text], |row| {
+            Ok(SearchHit {
+                session: row.get(0)?,
+                turn: row.get::<_, i64>(1)? as usize,
+                role: row.get(2)?,
+                snippet: row.get::<_, String>(3)?.replace('\n', " "),
+            })
+        })?;
+        let mut hits = Vec::new();
+        for row in rows {
+            hits.push(row?);
+        }
+        Ok(hits)
+    }
+}
@@ -0,0 +1,170 @@
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+// Streamed text is printed in batches rather than per token: at most every
+// STREAM_FLUSH_MS milliseconds, or sooner once STREAM_BUFFER_CHARS characters are waiting
+const DEFAULT_FLUSH_MS: u64 = 30;
+const DEFAULT_BUFFER_CHARS: usize = 512;
+
+// Client-side ceiling on a streamed response, in characters. This guards against endpoints
+// that keep streaming and never send [DONE]; it is deliberately far above any normal reply.
+const DEFAULT_MAX_STREAM_CHARS: usize = 200_000;
+
+pub fn max_stream_chars() -> usize {
+    env_number("STREAM_MAX_CHARS")
+        .filter(|&limit| limit > 0)
+        .unwrap_or(DEFAULT_MAX_STREAM_CHARS)
+}
+
+// RESPONSE_DEADLINE_SECS caps how long a turn may take in total, unlike a connection timeout
+pub fn response_deadline() -> Option<Duration> {
+    env_number::<f64>("RESPONSE_DEADLINE_SECS").filter(|&secs| secs > 0.0).map(Duration::from_secs_f64)
+}
+
+fn env_number<T: std::str::FromStr>(name: &str) -> Option<T> {
+    env::var(name).ok().and_then(|value| value.trim().parse().ok())
+}
+
+// Bounded buffer between network receipt and the terminal, so display pacing doesn't depend
+// on how fast (or how finely split) the stream arrives. A zero interval prints immediately.
+pub struct OutputBuffer {
+    pending: String,
+    pending_chars: usize,
+    capacity: usize,
+    interval: Duration,
+    last_flush: Instant,
+}
+
+impl OutputBuffer {
+    pub fn from_env() -> OutputBuffer {
+        OutputBuffer {
+            pending: String::new(),
+            pending_chars: 0,
+            capacity: env_number("STREAM_BUFFER_CHARS").filter(|&chars| chars > 0).unwrap_or(DEFAULT_BUFFER_CHARS),
+            interval: Duration::from_millis(env_number("STREAM_FLUSH_MS").unwrap_or(DEFAULT_FLUSH_MS)),
+            last_flush: Instant::now(),
+        }
+    }
+
+    pub fn push(&mut self, text: &str) -> io::Result<()> {
+        self.pending.push_str(text);
+        self.pending_chars += text.chars().count();
+        if self.pending_chars >= self.capacity || self.last_flush.elapsed() >= self.interval {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    // How long the next network read may wait before pending text is overdue for display
+    pub fn time_until_flush(&self) -> Option<Duration> {
+        if self.pending.is_empty() {
+            None
+        } else {
+            Some(self.interval.saturating_sub(self.last_flush.elapsed()))
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = Instant::now();
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let mut stdout = io::stdout();
+        stdout.write_all(self.pending.as_bytes())?;
+        stdout.flush()?;
+        self.pending.clear();
+        self.pending_chars = 0;
+        Ok(())
+    }
+}
+
+const PROGRESS_WIDTH: usize = 30;
+
+// Progress bars for capped streams are opt-in via STREAM_PROGRESS and need a terminal
+pub fn progress_enabled() -> bool {
+    let enabled = env::var("STREAM_PROGRESS").map(|value| value == "1" || value.eq_ignore_ascii_case("true")).unwrap_or(false);
+    enabled && io::stdout().is_terminal()
+}
+
+// Tokens received against the max_tokens cap, drawn in place on a single line
+pub struct ProgressBar {
+    cap: u64,
+    received: u64,
+}
+
+impl ProgressBar {
+    pub fn new(cap: u64) -> ProgressBar {
+        ProgressBar { cap, received: 0 }
+    }
+
+    pub fn advance(&mut self, tokens: u64) -> io::Result<()> {
+        self.received += tokens;
+        self.draw()
+    }
+
+    pub fn draw(&self) -> io::Result<()> {
+        let filled = (self.received.min(self.cap) * PROGRESS_WIDTH as u64 / self.cap) as usize;
+        let mut stdout = io::stdout();
+        write!(stdout, "\rReceiving [{}{}] {}/{} tokens", "#".repeat(filled), "-".repeat(PROGRESS_WIDTH - filled), self.received, self.cap)?;
+        stdout.flush()
+    }
+
+    // Erase the bar so the response can be printed on a clean line
+    pub fn clear(&self) -> io::Result<()> {
+        let mut stdout = io::stdout();
+        write!(stdout, "\r\x1b[K")?;
+        stdout.flush()
+    }
+}
+
+// Incremental parser for the server-sent events returned by streamed chat completions.
+// Network reads can split lines (and UTF-8 characters) anywhere, so bytes are buffered
+// until a full line is available and events are only emitted once they are complete.
+#[derive(Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    data: Vec<String>,
+}
+
+impl SseParser {
+    // Feed raw bytes from the network, returning the data payload of every completed event
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(bytes);
+
+        let mut events = Vec::new();
+        while let Some(newline) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+
+            if line.is_empty() {
+                // A blank line terminates the current event
+                if !self.data.is_empty() {
+                    events.push(self.data.join("\n"));
+                    self.data.clear();
+                }
+            } else if let Some(data) = line.strip_prefix("data:") {
+                self.data.push(data.strip_prefix(' ').unwrap_or(data).to_string());
+            }
+            // Comments (": keep-alive") and other SSE fields are ignored
+        }
+        events
+    }
+
+    // Flush an event left unterminated when the stream closes
+    pub fn finish(&mut self) -> Option<String> {
+        let rest: Vec<u8> = self.buffer.drain(..).collect();
+        let rest = String::from_utf8_lossy(&rest);
+        if let Some(data) = rest.trim_end().strip_prefix("data:") {
+            self.data.push(data.trim_start().to_string());
+        }
+        if self.data.is_empty() {
+            None
+        } else {
+            let event = self.data.join("\n");
+            self.data.clear();
+            Some(event)
+        }
+    }
+}
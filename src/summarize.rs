@@ -0,0 +1,56 @@
+use crate::images;
+use crate::query_gpt;
+use crate::ChatState;
+use serde_json::{json, Value};
+use std::env;
+
+// Older turns are folded into a system note starting with this, so a later summary can
+// fold the previous one in rather than stacking notes
+const SUMMARY_PREFIX: &str = "Summary of the earlier conversation:";
+// Most recent messages always kept verbatim
+const KEEP_RECENT: usize = 4;
+const SUMMARY_INSTRUCTION: &str = "Summarize the following conversation between a user and an assistant. Keep every fact, decision, name and open question that later turns may rely on. Write a compact paragraph or short list, with no preamble.";
+
+// AUTO_SUMMARIZE_AFTER=N summarizes automatically once the conversation has more than N user turns
+pub fn auto_summarize_after() -> Option<usize> {
+    env::var("AUTO_SUMMARIZE_AFTER").ok().and_then(|value| value.trim().parse().ok()).filter(|&turns| turns > 0)
+}
+
+pub fn user_turns(log: &[Value]) -> usize {
+    log.iter().filter(|message| message["role"] == "user").count()
+}
+
+fn is_summary(message: &Value) -> bool {
+    message["role"] == "system" && message["content"].as_str().is_some_and(|content| content.starts_with(SUMMARY_PREFIX))
+}
+
+// Replace everything but the system prompt and the most recent messages with a summary note.
+// Returns the number of messages folded, or 0 if there was nothing old enough to fold.
+pub async fn compress(state: &mut ChatState) -> Result<usize, Box<dyn std::error::Error>> {
+    let log = &state.conversation_log;
+    // The leading system prompt is never summarized away
+    let prompt_end = log.iter().position(|message| message["role"] != "system" || is_summary(message)).unwrap_or(log.len());
+    let fold_end = log.len().saturating_sub(KEEP_RECENT).max(prompt_end);
+    let folded = &log[prompt_end..fold_end];
+    if folded.iter().filter(|message| !is_summary(message)).count() == 0 {
+        return Ok(0);
+    }
+
+    let transcript: Vec<String> = folded
+        .iter()
+        .map(|message| format!("{}: {}", message["role"].as_str().unwrap_or("unknown"), images::text_of(&message["content"])))
+        .collect();
+    let request = vec![
+        json!({"role": "system", "content": SUMMARY_INSTRUCTION}),
+        json!({"role": "user", "content": transcript.join("\n\n")}),
+    ];
+    let summary = query_gpt(&request, state.provider, &state.model, state.verbose).await?;
+    if summary.trim().is_empty() {
+        return Err("the model returned an empty summary".into());
+    }
+
+    let count = folded.len();
+    let note = json!({"role": "system", "content": format!("{} {}", SUMMARY_PREFIX, summary.trim())});
+    state.conversation_log.splice(prompt_end..fold_end, [note]);
+    Ok(count)
+}
@@ -0,0 +1,29 @@
+use crate::input;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use tiktoken_rs::CoreBPE;
+
+// Encoder per model name, or None for models tiktoken has no encoding for. Encoders are built
+// once and shared, so only the first count for a model pays for loading the ranks.
+static ENCODERS: OnceLock<Mutex<HashMap<String, Option<&'static CoreBPE>>>> = OnceLock::new();
+
+fn encoder(model: &str) -> Option<&'static CoreBPE> {
+    let mut encoders = ENCODERS.get_or_init(Default::default).lock().unwrap();
+    *encoders.entry(model.to_string()).or_insert_with(|| {
+        // Routed names like openai/gpt-4o use the encoding of the model after the slash
+        let name = model.rsplit('/').next().unwrap_or(model);
+        let found = tiktoken_rs::bpe_for_model(name).ok();
+        if found.is_none() {
+            eprintln!("Warning: no tokenizer is known for {}; token counts are estimated", model);
+        }
+        found
+    })
+}
+
+// Tokens `text` encodes to for `model`, or the heuristic estimate when its encoding isn't known
+pub fn count_tokens(model: &str, text: &str) -> usize {
+    match encoder(model) {
+        Some(bpe) => bpe.encode_ordinary(text).len(),
+        None => input::estimate_tokens(text),
+    }
+}
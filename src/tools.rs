@@ -0,0 +1,158 @@
+use chrono::Local;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Component, Path};
+use std::sync::{Mutex, OnceLock};
+
+// A local function the model can call. It gets the parsed arguments and returns the text sent
+// back as the tool result; an Err is sent back too, so the model can recover from it.
+pub type Handler = fn(&Value) -> Result<String, String>;
+
+pub struct Tool {
+    pub description: &'static str,
+    // JSON schema of the arguments
+    pub parameters: Value,
+    pub handler: Handler,
+}
+
+// Larger files are cut off so one call can't flood the context window
+const MAX_READ_BYTES: usize = 64 * 1024;
+// How many rounds of tool calls a single response may take before giving up
+pub const MAX_TOOL_ROUNDS: usize = 5;
+
+// The built-in tools by name
+pub fn registry() -> HashMap<String, Tool> {
+    let mut tools = HashMap::new();
+    tools.insert("get_time".to_string(), Tool {
+        description: "Get the current local date and time",
+        parameters: json!({"type": "object", "properties": {}}),
+        handler: get_time,
+    });
+    tools.insert("read_file".to_string(), Tool {
+        description: "Read a text file from the current directory",
+        parameters: json!({
+            "type": "object",
+            "properties": {"path": {"type": "string", "description": "Path relative to the current directory"}},
+            "required": ["path"],
+        }),
+        handler: read_file,
+    });
+    tools
+}
+
+fn get_time(_arguments: &Value) -> Result<String, String> {
+    Ok(Local::now().format("%A %Y-%m-%d %H:%M:%S %:z").to_string())
+}
+
+// Only relative paths that stay inside the current directory can be read, checked again after
+// symlinks are resolved. Dotfiles (.env with the API keys, .git, ...) are never read.
+fn read_file(arguments: &Value) -> Result<String, String> {
+    let path = arguments["path"].as_str().ok_or("missing 'path' argument")?;
+    let path = Path::new(path);
+    if !path.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)) {
+        return Err(format!("{} is outside the current directory", path.display()));
+    }
+    let resolved = path.canonicalize().map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+    let cwd = env::current_dir().and_then(|cwd| cwd.canonicalize()).map_err(|err| format!("can't resolve the current directory: {}", err))?;
+    let inside = match resolved.strip_prefix(&cwd) {
+        Ok(inside) => inside,
+        Err(_) => return Err(format!("{} is outside the current directory", path.display())),
+    };
+    if is_hidden(path) || is_hidden(inside) {
+        return Err(format!("{} is a hidden file, which can't be read", path.display()));
+    }
+    let data = fs::read(&resolved).map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+    let mut text = String::from_utf8_lossy(&data[..data.len().min(MAX_READ_BYTES)]).into_owned();
+    if data.len() > MAX_READ_BYTES {
+        text.push_str(&format!("\n[truncated at {} bytes]", MAX_READ_BYTES));
+    }
+    Ok(text)
+}
+
+// Whether any part of the path is a dotfile or dot-directory
+fn is_hidden(path: &Path) -> bool {
+    path.components().any(|component| matches!(component, Component::Normal(name) if name.to_string_lossy().starts_with('.')))
+}
+
+// TOOLS=all, or a comma-separated list of tool names, offers those tools with chat turns
+fn enabled_names() -> Vec<String> {
+    let value = env::var("TOOLS").unwrap_or_default();
+    let names: Vec<&str> = value.split(',').map(str::trim).filter(|name| !name.is_empty()).collect();
+    let registry = registry();
+    if names.iter().any(|name| name.eq_ignore_ascii_case("all")) {
+        let mut all: Vec<String> = registry.into_keys().collect();
+        all.sort();
+        return all;
+    }
+    names.into_iter().filter(|name| {
+        let known = registry.contains_key(*name);
+        if !known {
+            eprintln!("Ignoring unknown tool in TOOLS: {}", name);
+        }
+        known
+    }).map(str::to_string).collect()
+}
+
+// The `tools` array for a chat request, or None when no tool is enabled. TOOLS is read once,
+// so an unknown name is only reported at the first request.
+pub fn definitions() -> Option<Value> {
+    static DEFINITIONS: OnceLock<Option<Value>> = OnceLock::new();
+    DEFINITIONS.get_or_init(|| {
+        let names = enabled_names();
+        if names.is_empty() {
+            return None;
+        }
+        let registry = registry();
+        let tools: Vec<Value> = names.iter().filter_map(|name| registry.get(name).map(|tool| (name, tool))).map(|(name, tool)| json!({
+            "type": "function",
+            "function": {"name": name, "description": tool.description, "parameters": tool.parameters},
+        })).collect();
+        Some(json!(tools))
+    }).clone()
+}
+
+// Run one entry of a response's `tool_calls` and build the `tool` message answering it
+pub fn dispatch(call: &Value) -> Value {
+    let name = call["function"]["name"].as_str().unwrap_or_default();
+    // Arguments arrive as a JSON-encoded string; an empty one means no arguments
+    let arguments = call["function"]["arguments"].as_str().filter(|arguments| !arguments.trim().is_empty()).unwrap_or("{}");
+    let result = match (registry().get(name), serde_json::from_str::<Value>(arguments)) {
+        (None, _) => Err(format!("unknown tool '{}'", name)),
+        (Some(_), Err(err)) => Err(format!("invalid arguments: {}", err)),
+        (Some(tool), Ok(arguments)) => (tool.handler)(&arguments),
+    };
+    let content = result.unwrap_or_else(|err| format!("Error: {}", err));
+    json!({"role": "tool", "tool_call_id": call["id"], "content": content})
+}
+
+// Whether the model may issue several tool calls in one turn. None leaves it to the API
+// default; Some(false) forces one call at a time for deterministic sequencing.
+static PARALLEL_TOOL_CALLS: Mutex<Option<Option<bool>>> = Mutex::new(None);
+
+// PARALLEL_TOOL_CALLS=true|false, unset for the API default
+fn parallel_from_env() -> Option<bool> {
+    match env::var("PARALLEL_TOOL_CALLS").unwrap_or_default().to_ascii_lowercase().as_str() {
+        "1" | "true" | "on" => Some(true),
+        "0" | "false" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+pub fn parallel_tool_calls() -> Option<bool> {
+    *PARALLEL_TOOL_CALLS.lock().unwrap().get_or_insert_with(parallel_from_env)
+}
+
+pub fn set_parallel_tool_calls(value: Option<bool>) {
+    *PARALLEL_TOOL_CALLS.lock().unwrap() = Some(value);
+}
+
+// Add tool-related request options. They are only valid alongside tools, so a body without
+// any is left untouched.
+pub fn apply_options(body: &mut Value) {
+    let has_tools = body["tools"].as_array().is_some_and(|tools| !tools.is_empty());
+    if let (true, Some(parallel)) = (has_tools, parallel_tool_calls()) {
+        body["parallel_tool_calls"] = json!(parallel);
+    }
+}
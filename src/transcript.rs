@@ -0,0 +1,42 @@
+use crate::files;
+use chrono::Local;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const TRANSCRIPT_DIR: &str = "logs";
+
+// Markdown record of each message as it is sent or received, one file per run. Unlike the
+// conversation log it is only ever appended to, so edits, /clear and /load don't rewrite it.
+pub struct Transcript {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl Transcript {
+    pub fn new() -> Transcript {
+        let name = format!("session-{}.md", Local::now().format("%Y%m%d-%H%M%S"));
+        Transcript { path: Path::new(TRANSCRIPT_DIR).join(name), file: None }
+    }
+
+    // Append a message under its role and the time it was logged. Failures are reported but
+    // never interrupt the chat.
+    pub fn record(&mut self, role: &str, content: &str) {
+        if let Err(err) = self.append(role, content) {
+            eprintln!("Failed to write transcript {}: {}", self.path.display(), err);
+        }
+    }
+
+    // The file is created on the first message, so a run with nothing said leaves none behind.
+    // Each entry is synced to disk before returning.
+    fn append(&mut self, role: &str, content: &str) -> Result<(), io::Error> {
+        if self.file.is_none() {
+            files::confirm_write(&self.path)?;
+            fs::create_dir_all(TRANSCRIPT_DIR)?;
+            self.file = Some(OpenOptions::new().create(true).append(true).open(&self.path)?);
+        }
+        let file = self.file.as_mut().expect("transcript file was just opened");
+        write!(file, "### {} ({})\n\n{}\n\n", role, Local::now().format("%Y-%m-%d %H:%M:%S"), content.trim_end())?;
+        file.sync_data()
+    }
+}
@@ -0,0 +1,33 @@
+use crate::images;
+use crate::tokens;
+use serde_json::Value;
+
+// Per-message overhead the API adds for role and framing, on top of the content itself
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+// Tokens for one message as `model` counts them
+pub fn message_tokens(model: &str, message: &Value) -> usize {
+    MESSAGE_OVERHEAD_TOKENS + tokens::count_tokens(model, &images::text_of(&message["content"]))
+}
+
+pub fn log_tokens(model: &str, log: &[Value]) -> usize {
+    log.iter().map(|message| message_tokens(model, message)).sum()
+}
+
+// Drop the oldest non-system messages until the log fits the budget. System messages (the
+// prompt, summaries, addenda) and the final message (the one being answered) always stay, so
+// the result can still be over budget. Returns how many messages were dropped.
+pub fn trim_to_budget(log: &mut Vec<Value>, budget: usize, model: &str) -> usize {
+    let mut total = log_tokens(model, log);
+    let mut dropped = 0;
+    while total > budget {
+        let last = log.len().saturating_sub(1);
+        let oldest = match log[..last].iter().position(|message| message["role"] != "system") {
+            Some(index) => index,
+            None => break,
+        };
+        total -= message_tokens(model, &log.remove(oldest));
+        dropped += 1;
+    }
+    dropped
+}
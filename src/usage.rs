@@ -0,0 +1,86 @@
+use serde_json::Value;
+use std::env;
+use std::sync::Mutex;
+
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+// Token accounting reported by the API for a single completion
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
+impl Usage {
+    // Parse a `usage` object, which is null on every streamed chunk except the last
+    pub fn from_json(value: &Value) -> Option<Usage> {
+        let prompt_tokens = value["prompt_tokens"].as_u64()?;
+        let completion_tokens = value["completion_tokens"].as_u64()?;
+        Some(Usage {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: value["total_tokens"].as_u64().unwrap_or(prompt_tokens + completion_tokens),
+        })
+    }
+}
+
+// USD per million prompt and completion tokens. Dated snapshots (gpt-4o-2024-08-06) and
+// provider-prefixed ids (openai/gpt-4o) match their base model; the longest prefix wins, so
+// gpt-4o-mini isn't priced as gpt-4o.
+const PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-3.5-turbo", 0.50, 1.50),
+    ("gpt-4", 30.00, 60.00),
+    ("gpt-4-turbo", 10.00, 30.00),
+    ("gpt-4o", 2.50, 10.00),
+    ("gpt-4o-mini", 0.15, 0.60),
+    ("gpt-4.1", 2.00, 8.00),
+    ("gpt-4.1-mini", 0.40, 1.60),
+    ("gpt-4.1-nano", 0.10, 0.40),
+    ("o3-mini", 1.10, 4.40),
+];
+
+// Running totals for this run, across every chat turn
+static SESSION: Mutex<(Usage, f64)> = Mutex::new((Usage { prompt_tokens: 0, completion_tokens: 0, total_tokens: 0 }, 0.0));
+
+// Estimated cost of a completion, or None for models without a known price
+pub fn cost(model: &str, usage: &Usage) -> Option<f64> {
+    let model = model.rsplit('/').next().unwrap_or(model);
+    let (_, prompt, completion) = PRICES.iter().filter(|(name, _, _)| model.starts_with(name)).max_by_key(|(name, _, _)| name.len())?;
+    Some((usage.prompt_tokens as f64 * prompt + usage.completion_tokens as f64 * completion) / 1_000_000.0)
+}
+
+// Add a turn's usage to the session totals, and show it as a dim line when `show` is set
+pub fn record(model: &str, usage: &Usage, show: bool) {
+    let turn_cost = cost(model, usage);
+    let mut session = SESSION.lock().unwrap();
+    session.0.prompt_tokens += usage.prompt_tokens;
+    session.0.completion_tokens += usage.completion_tokens;
+    session.0.total_tokens += usage.total_tokens;
+    session.1 += turn_cost.unwrap_or(0.0);
+    if !show {
+        return;
+    }
+    let mut line = format!("[prompt {} / completion {} / total {}", usage.prompt_tokens, usage.completion_tokens, usage.total_tokens);
+    if let Some(turn_cost) = turn_cost {
+        line.push_str(&format!(" | ${:.4}, session ${:.4}", turn_cost, session.1));
+    }
+    line.push(']');
+    println!("{}{}{}", DIM, line, RESET);
+}
+
+// Carry on from the totals saved with a session
+pub fn restore(usage: Usage, cost: f64) {
+    *SESSION.lock().unwrap() = (usage, cost);
+}
+
+// Totals so far this run, with the estimated cost of the priced turns
+pub fn session_totals() -> (Usage, f64) {
+    *SESSION.lock().unwrap()
+}
+
+// SHOW_USAGE=1 shows the usage line after every response; /usage toggles it
+pub fn show_from_env() -> bool {
+    env::var("SHOW_USAGE").map(|value| value == "1" || value.eq_ignore_ascii_case("true")).unwrap_or(false)
+}
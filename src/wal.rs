@@ -0,0 +1,131 @@
+use crate::files;
+use crate::storage;
+use serde_json::{json, Value};
+use std::env;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// Append-only journal of the conversation, so a crash loses at most the message being
+// written. Each line is one record:
+//   {"base": "<session>"}  start from the stored session of that name
+//   {"append": <message>}  a message added to the end of the log
+//   {"reset": [<message>]} the log was changed some other way and is replaced wholesale
+// A clean save compacts the journal to a single base record; a clean exit removes it, so a
+// journal left behind at startup means the last run crashed.
+const DEFAULT_WAL_PATH: &str = "conversations/.wal.jsonl";
+
+// Journaling is opt-in via WAL=1; WAL_PATH overrides where it is kept
+pub fn enabled() -> bool {
+    env::var("WAL").map(|value| value == "1" || value.eq_ignore_ascii_case("true")).unwrap_or(false)
+}
+
+fn wal_path() -> PathBuf {
+    PathBuf::from(env::var("WAL_PATH").unwrap_or_else(|_| DEFAULT_WAL_PATH.to_string()))
+}
+
+pub fn crash_detected() -> bool {
+    enabled() && wal_path().exists()
+}
+
+// Rebuild the conversation, and the session it belonged to, by replaying the journal
+pub fn recover() -> Result<(Vec<Value>, Option<String>), Box<dyn Error>> {
+    let data = fs::read_to_string(wal_path())?;
+    let mut log = Vec::new();
+    let mut session = None;
+    for (number, line) in data.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        let record: Value = match serde_json::from_str(line) {
+            Ok(record) => record,
+            // A torn final line is expected after a crash; anything before it is still good
+            Err(err) => {
+                eprintln!("Stopping replay at journal line {}: {}", number + 1, err);
+                break;
+            }
+        };
+        if let Some(name) = record["base"].as_str() {
+            log = storage::open_store()?.load(name)?.unwrap_or_default();
+            session = Some(name.to_string());
+        } else if let Some(messages) = record["reset"].as_array() {
+            log = messages.clone();
+        } else if !record["append"].is_null() {
+            log.push(record["append"].clone());
+        }
+    }
+    Ok((log, session))
+}
+
+pub struct Wal {
+    path: PathBuf,
+    file: Option<File>,
+    // The log as the journal currently describes it
+    synced: Vec<Value>,
+    active: bool,
+}
+
+impl Wal {
+    // A journal for this run. It stays inactive when disabled, or when an earlier crash left a
+    // journal that hasn't been recovered yet, so that journal is never overwritten.
+    pub fn new(active: bool) -> Wal {
+        Wal { path: wal_path(), file: None, synced: Vec::new(), active }
+    }
+
+    // Bring the journal in line with the log: new trailing messages are appended, any
+    // other change is written as a reset
+    pub fn sync(&mut self, log: &[Value]) {
+        if !self.active || log == self.synced.as_slice() {
+            return;
+        }
+        let records: Vec<Value> = if log.starts_with(&self.synced) {
+            log[self.synced.len()..].iter().map(|message| json!({"append": message})).collect()
+        } else {
+            vec![json!({"reset": log})]
+        };
+        match self.write(&records, false) {
+            Ok(()) => self.synced = log.to_vec(),
+            Err(err) => eprintln!("Failed to write conversation journal {}: {}", self.path.display(), err),
+        }
+    }
+
+    // After a clean save the session file holds everything, so the journal restarts from it
+    pub fn compact(&mut self, session: &str, log: &[Value]) {
+        if !self.active {
+            return;
+        }
+        self.file = None;
+        match self.write(&[json!({"base": session})], true) {
+            Ok(()) => self.synced = log.to_vec(),
+            Err(err) => eprintln!("Failed to compact conversation journal {}: {}", self.path.display(), err),
+        }
+    }
+
+    // Clean exit: nothing left to recover
+    pub fn remove(&mut self) {
+        if !self.active {
+            return;
+        }
+        self.file = None;
+        if let Err(err) = fs::remove_file(&self.path) {
+            if err.kind() != io::ErrorKind::NotFound {
+                eprintln!("Failed to remove conversation journal {}: {}", self.path.display(), err);
+            }
+        }
+    }
+
+    // Append records, syncing them to disk. The file is (re)created on first use in a run or
+    // when `truncate` is set; safe mode confirms that once rather than on every message.
+    fn write(&mut self, records: &[Value], truncate: bool) -> Result<(), io::Error> {
+        if self.file.is_none() || truncate {
+            files::confirm_write(&self.path)?;
+            if let Some(parent) = Path::new(&self.path).parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                fs::create_dir_all(parent)?;
+            }
+            self.file = Some(OpenOptions::new().create(true).write(true).truncate(true).open(&self.path)?);
+        }
+        let file = self.file.as_mut().expect("journal file was just opened");
+        for record in records {
+            writeln!(file, "{}", record)?;
+        }
+        file.sync_data()
+    }
+}